@@ -1,68 +1,56 @@
-extern crate futures;
 extern crate hyper;
 extern crate pretty_env_logger;
 extern crate radix_router;
 
-use futures::future;
-use hyper::rt::{self, Future, Stream};
-use hyper::service::service_fn;
-use hyper::{Body, Request, Response, Server};
-use radix_router::router::{BoxFut, Handler, Params, Router};
-use std::sync::Arc;
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use radix_router::fs::ServeDir;
+use radix_router::router::{boxed, full, Body, BoxError, Handler, Params, Router};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
 
-fn get_echo(_: Request<Body>, _: Params) -> BoxFut {
-    // Box::new(future::ok(Response::new(Body::from("Try POSTing data to /echo"))))
-    // *response.body_mut() = Body::from("Try POSTing data to /echo");
-    let response = Response::builder()
-        .body(Body::from("Try POSTing data to /echo"))
-        .unwrap();
-    Box::new(future::ok(response))
+async fn get_echo(_: Request<Incoming>, _: Params) -> Result<Response<Body>, Infallible> {
+    Ok(Response::new(full("Try POSTing data to /echo")))
 }
 
-fn post_echo(req: Request<Body>, _: Params) -> BoxFut {
-    // Box::new(future::ok(Response::new(req.into_body())))
-    // *response.body_mut() = req.into_body();
-    let response = Response::builder().body(req.into_body()).unwrap();
-    Box::new(future::ok(response))
+async fn post_echo(req: Request<Incoming>, _: Params) -> Result<Response<Incoming>, Infallible> {
+    Ok(Response::new(req.into_body()))
 }
 
-fn post_echo_uppercase(req: Request<Body>, _: Params) -> BoxFut {
-    let mapping = req.into_body().map(|chunk| {
-        chunk
-            .iter()
-            .map(|byte| byte.to_ascii_uppercase())
-            .collect::<Vec<u8>>()
+async fn post_echo_uppercase(
+    req: Request<Incoming>,
+    _: Params,
+) -> Result<Response<Body>, Infallible> {
+    let mapping = req.into_body().map_frame(|frame| {
+        frame.map_data(|chunk| {
+            chunk
+                .iter()
+                .map(|byte| byte.to_ascii_uppercase())
+                .collect::<Bytes>()
+        })
     });
 
-    // *response.body_mut() = Body::wrap_stream(mapping);
-    let response = Response::builder()
-        .body(Body::wrap_stream(mapping))
-        .unwrap();
-    Box::new(future::ok(response))
+    Ok(Response::new(boxed(mapping)))
 }
 
-fn post_echo_reversed(req: Request<Body>, _: Params) -> BoxFut {
-    let reversed = req
-        .into_body()
-        .concat2()
-        .map(move |chunk| {
-            let body = chunk.iter().rev().cloned().collect::<Vec<u8>>();
-            // *response.body_mut() = Body::from(body);
-            // response
-            Response::builder().body(Body::from(body)).unwrap()
-        })
-        .map_err(|e| e.into());
-    Box::new(reversed)
+async fn post_echo_reversed(req: Request<Incoming>, _: Params) -> Result<Response<Body>, BoxError> {
+    let whole = req.into_body().collect().await?.to_bytes();
+    let body = whole.iter().rev().cloned().collect::<Vec<u8>>();
+    Ok(Response::new(full(body)))
 }
 
-fn main() {
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
     pretty_env_logger::init();
 
-    let addr = ([127, 0, 0, 1], 3000).into();
+    let addr: SocketAddr = ([127, 0, 0, 1], 3000).into();
     let some_str = "Some";
 
-    // new_service is run for each connection, creating a 'service'
-    // to handle requests for that specific connection.
     let mut router: Router<Handler> = Router::new();
     router.get("/", Box::new(get_echo));
     router.post("/echo", Box::new(post_echo));
@@ -70,36 +58,28 @@ fn main() {
     router.post("/echo/reversed", Box::new(post_echo_reversed));
     router.get(
         "/some",
-        Box::new(move |_, _| -> BoxFut {
-            Box::new(future::ok(
-                Response::builder().body(some_str.into()).unwrap(),
-            ))
+        Box::new(move |_: Request<Incoming>, _: Params| async move {
+            Ok::<_, Infallible>(Response::new(full(some_str)))
         }),
     );
-    router.serve_files("/examples/*filepath", "examples");
-    let arc_router = Arc::new(router);
-
-    let new_service = move || {
-        // This is the `Service` that will handle the connection.
-        // `service_fn_ok` is a helper to convert a function that
-        // returns a Response into a `Service`.
-        // service_fn_ok(|_| {
-        //     Response::new(Body::from(PHRASE))
-        // })
-
-        // router.get("/some", |req, ps| {
-        //     Box::new(future::ok(Response::new(Body::empty())))
-        // });
-
-        let router = arc_router.clone();
-        service_fn(move |req| router.serve_http(req))
-    };
-
-    let server = Server::bind(&addr)
-        .serve(new_service)
-        .map_err(|e| eprintln!("server error: {}", e));
+    // GET /examples/ serves examples/send_file_index.html
+    router.serve_dir(
+        "/examples/*filepath",
+        ServeDir::new("examples").index_file("send_file_index.html"),
+    );
+    let service = router.into_service();
 
+    let listener = TcpListener::bind(addr).await?;
     println!("Listening on http://{}", addr);
 
-    rt::run(server);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let conn = http1::Builder::new().serve_connection(TokioIo::new(stream), service.clone());
+        // Each connection is served on its own task.
+        tokio::spawn(async move {
+            if let Err(e) = conn.await {
+                eprintln!("server error: {}", e);
+            }
+        });
+    }
 }
@@ -1,51 +1,47 @@
-extern crate futures;
 extern crate hyper;
 extern crate pretty_env_logger;
 extern crate radix_router;
 
-use futures::future;
-use hyper::rt::{self, Future};
-use hyper::service::service_fn;
-use hyper::{Body, Request, Response, Server};
-use radix_router::router::{BoxFut, Handler, Params, Router};
-use std::sync::Arc;
-
-fn index(_: Request<Body>, _: Params) -> BoxFut {
-    let res = Response::builder().body("welcome!\n".into()).unwrap();
-
-    Box::new(future::ok(res))
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use radix_router::router::{full, Body, BoxError, Handler, Params, Router};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+async fn index(_: Request<Incoming>, _: Params) -> Result<Response<Body>, Infallible> {
+    Ok(Response::new(full("welcome!\n")))
 }
 
-fn hello(_: Request<Body>, ps: Params) -> BoxFut {
+async fn hello(_: Request<Incoming>, ps: Params) -> Result<Response<Body>, Infallible> {
     // let name = ps.by_name("name").unwrap();
     let name = &ps[0];
-    let res = Response::builder()
-        .body(format!("hello, {}!\n", name).into())
-        .unwrap();
-    Box::new(future::ok(res))
+    Ok(Response::new(full(format!("hello, {}!\n", name))))
 }
 
-fn main() {
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
     pretty_env_logger::init();
 
-    let addr = ([127, 0, 0, 1], 3000).into();
+    let addr: SocketAddr = ([127, 0, 0, 1], 3000).into();
     let mut router: Router<Handler> = Router::new();
     router.get("/", Box::new(index));
     router.get("/hello/:name", Box::new(hello));
-    let arc_router = Arc::new(router);
-    // new_service is run for each connection, creating a 'service'
-    // to handle requests for that specific connection.
-    let new_service = move || {
-        // This is the `Service` that will handle the connection.
-        let router = arc_router.clone();
-        service_fn(move |req| router.serve_http(req))
-    };
-
-    let server = Server::bind(&addr)
-        .serve(new_service)
-        .map_err(|e| eprintln!("server error: {}", e));
+    let service = router.into_service();
 
+    let listener = TcpListener::bind(addr).await?;
     println!("Listening on http://{}", addr);
 
-    rt::run(server);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let conn = http1::Builder::new().serve_connection(TokioIo::new(stream), service.clone());
+        // Each connection is served on its own task.
+        tokio::spawn(async move {
+            if let Err(e) = conn.await {
+                eprintln!("server error: {}", e);
+            }
+        });
+    }
 }
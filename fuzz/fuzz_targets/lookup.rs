@@ -0,0 +1,49 @@
+//! Looks up arbitrary bytes, which must never panic.
+//!
+//! Run with `cargo +nightly fuzz run lookup` from the repository root.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use radix_router::tree::Node;
+use std::sync::OnceLock;
+
+fn tree() -> &'static Node<&'static str> {
+    static TREE: OnceLock<Node<&'static str>> = OnceLock::new();
+
+    TREE.get_or_init(|| {
+        let mut tree = Node::new();
+        for route in &[
+            "/",
+            "/hi",
+            "/users/new",
+            "/users/:id<\\d+>",
+            "/users/:id<\\d+>/posts/:slug",
+            "/users/*rest",
+            "/src/*filepath",
+            "/u/äpfêl/",
+            "/w/♬",
+            "/w/♭/",
+            "/w/𠜎",
+            "/Π",
+            "/:page",
+        ] {
+            tree.add_route(route, *route);
+        }
+        tree
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let tree = tree();
+
+    let valid = std::str::from_utf8(data).is_ok();
+    assert_eq!(valid, tree.get_value_bytes(data).is_ok());
+    assert_eq!(
+        valid,
+        tree.find_case_insensitive_path_bytes(data, true).is_ok()
+    );
+
+    let lossy = String::from_utf8_lossy(data);
+    tree.get_value(&lossy);
+    tree.find_case_insensitive_path(&lossy, false);
+});
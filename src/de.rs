@@ -0,0 +1,412 @@
+//! Deserializing `Params` with serde, enabled by the `serde` feature.
+
+use crate::router::{Param, ParamError, Params};
+use serde::de::value::BorrowedStrDeserializer;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use std::fmt;
+use std::slice;
+
+impl de::Error for ParamError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ParamError::Custom {
+            reason: msg.to_string(),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        ParamError::Missing {
+            name: field.to_string(),
+        }
+    }
+}
+
+impl Params {
+    /// Deserializes the params into `T`, e.g. a struct with a field per param,
+    /// a tuple of the param values in order, or a single value if there is
+    /// exactly one param.
+    ///
+    /// ```
+    /// use radix_router::router::{Param, Params};
+    /// use serde_derive::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct UserPath {
+    ///     id: u64,
+    ///     tab: String,
+    /// }
+    ///
+    /// let params = Params(vec![Param::new("id", "42"), Param::new("tab", "posts")]);
+    /// let p: UserPath = params.deserialize().unwrap();
+    /// assert_eq!(42, p.id);
+    /// assert_eq!("posts", p.tab);
+    /// ```
+    pub fn deserialize<'de, T: de::Deserialize<'de>>(&'de self) -> Result<T, ParamError> {
+        T::deserialize(ParamsDeserializer { params: self })
+    }
+}
+
+/// attaches the param to errors raised while deserializing its value
+fn named<T>(param: &Param, res: Result<T, ParamError>) -> Result<T, ParamError> {
+    res.map_err(|err| match err {
+        ParamError::Custom { reason } => ParamError::Invalid {
+            name: param.key.clone(),
+            value: param.value.clone(),
+            reason,
+        },
+        err => err,
+    })
+}
+
+struct ParamsDeserializer<'de> {
+    params: &'de Params,
+}
+
+impl<'de> ParamsDeserializer<'de> {
+    /// returns the only param, for deserializing a single value
+    fn single(&self) -> Result<&'de Param, ParamError> {
+        match self.params.0.as_slice() {
+            [param] => Ok(param),
+            params => Err(de::Error::custom(format!(
+                "expected a single param, found {}",
+                params.len()
+            ))),
+        }
+    }
+}
+
+macro_rules! single_value {
+    ($($deserialize:ident)*) => {$(
+        fn $deserialize<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+            let param = self.single()?;
+            named(param, ValueDeserializer { value: &param.value }.$deserialize(visitor))
+        }
+    )*};
+}
+
+impl<'de> Deserializer<'de> for ParamsDeserializer<'de> {
+    type Error = ParamError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+        visitor.visit_map(ParamsMap {
+            iter: self.params.0.iter(),
+            value: None,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+        visitor.visit_seq(ParamsSeq {
+            iter: self.params.0.iter(),
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        if len != self.params.0.len() {
+            return Err(de::Error::custom(format!(
+                "expected {} params, found {}",
+                len,
+                self.params.0.len()
+            )));
+        }
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        let param = self.single()?;
+        named(
+            param,
+            ValueDeserializer {
+                value: &param.value,
+            }
+            .deserialize_enum(name, variants, visitor),
+        )
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+        visitor.visit_unit()
+    }
+
+    single_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_option deserialize_identifier
+    }
+}
+
+struct ParamsMap<'de> {
+    iter: slice::Iter<'de, Param>,
+    // the param whose key was deserialized last
+    value: Option<&'de Param>,
+}
+
+impl<'de> MapAccess<'de> for ParamsMap<'de> {
+    type Error = ParamError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ParamError> {
+        match self.iter.next() {
+            Some(param) => {
+                self.value = Some(param);
+                seed.deserialize(BorrowedStrDeserializer::new(&param.key))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ParamError> {
+        match self.value.take() {
+            Some(param) => named(
+                param,
+                seed.deserialize(ValueDeserializer {
+                    value: &param.value,
+                }),
+            ),
+            None => Err(de::Error::custom("value is missing")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct ParamsSeq<'de> {
+    iter: slice::Iter<'de, Param>,
+}
+
+impl<'de> SeqAccess<'de> for ParamsSeq<'de> {
+    type Error = ParamError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, ParamError> {
+        match self.iter.next() {
+            Some(param) => named(
+                param,
+                seed.deserialize(ValueDeserializer {
+                    value: &param.value,
+                }),
+            )
+            .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// Deserializes the value of a single param. Numbers, bools and chars are
+/// parsed with `FromStr`.
+struct ValueDeserializer<'de> {
+    value: &'de str,
+}
+
+macro_rules! parse_value {
+    ($($deserialize:ident => $visit:ident,)*) => {$(
+        fn $deserialize<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+            match self.value.parse() {
+                Ok(value) => visitor.$visit(value),
+                Err(err) => Err(de::Error::custom(err)),
+            }
+        }
+    )*};
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = ParamError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+        visitor.visit_borrowed_str(self.value)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ParamError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ParamError> {
+        visitor.visit_enum(self.value.into_deserializer())
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::router::{Param, ParamError, Params};
+    use serde_derive::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct UserPath {
+        id: u64,
+        name: String,
+        tab: Option<Tab>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Tab {
+        Posts,
+        Friends,
+    }
+
+    #[test]
+    fn deserialize_struct() {
+        let params = Params(vec![
+            Param::new("id", "42"),
+            Param::new("name", "gordon"),
+            Param::new("tab", "posts"),
+        ]);
+
+        assert_eq!(
+            Ok(UserPath {
+                id: 42,
+                name: "gordon".to_string(),
+                tab: Some(Tab::Posts),
+            }),
+            params.deserialize()
+        );
+
+        let params = Params(vec![Param::new("id", "42"), Param::new("name", "gordon")]);
+        assert_eq!(None, params.deserialize::<UserPath>().unwrap().tab);
+    }
+
+    #[test]
+    fn deserialize_values() {
+        let params = Params(vec![Param::new("id", "42"), Param::new("name", "gordon")]);
+
+        assert_eq!(Ok((42, "gordon")), params.deserialize::<(u32, &str)>());
+        assert_eq!(
+            Ok(vec!["42".to_string(), "gordon".to_string()]),
+            params.deserialize::<Vec<String>>()
+        );
+        assert!(params.deserialize::<(u32,)>().is_err());
+        assert!(params.deserialize::<u32>().is_err());
+
+        let params = Params(vec![Param::new("id", "42")]);
+        assert_eq!(Ok(42), params.deserialize::<u32>());
+    }
+
+    #[test]
+    fn deserialize_errors() {
+        let params = Params(vec![Param::new("id", "x42"), Param::new("name", "gordon")]);
+        match params.deserialize::<UserPath>() {
+            Err(ParamError::Invalid { name, value, .. }) => {
+                assert_eq!("id", name);
+                assert_eq!("x42", value);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let params = Params(vec![Param::new("id", "42")]);
+        assert_eq!(
+            Err(ParamError::Missing {
+                name: "name".to_string()
+            }),
+            params.deserialize::<UserPath>()
+        );
+
+        let params = Params(vec![
+            Param::new("id", "42"),
+            Param::new("name", "gordon"),
+            Param::new("tab", "likes"),
+        ]);
+        match params.deserialize::<UserPath>() {
+            Err(ParamError::Invalid { name, .. }) => assert_eq!("tab", name),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,2146 @@
+//! Static files, as served by `Router::serve_files` and `Router::serve_dir`.
+//!
+//! Files are streamed in chunks instead of being read into memory. Responses
+//! carry a `Content-Type` guessed from the extension, `Last-Modified` and
+//! `ETag` validators, and answer conditional requests with 304 (Not Modified)
+//! or 412 (Precondition Failed). Single and multiple byte ranges are served
+//! with 206 (Partial Content), or 416 (Range Not Satisfiable).
+//!
+//! Paths requested below a root directory are checked by `resolve` before any
+//! file is opened, so they can not escape the root. `ServeDir` configures how
+//! directories, hidden files and missing files below the root are served.
+//!
+//! `ServeDir` negotiates the Accept-Encoding of the request, serving the
+//! precompressed siblings of files, like "app.js.br" and "app.js.gz", when
+//! they exist. Behind the `compression` feature, it can compress other text
+//! files with gzip while sending them.
+
+use crate::path::{escape_path, unescape_path};
+use crate::router::{boxed, empty, full, Body, BoxError, Handler, Params, Redirects, Router};
+use crate::service::OriginalUri;
+use bytes::{Bytes, BytesMut};
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+use http_body::{Frame, SizeHint};
+use httpdate::HttpDate;
+use hyper::body::Incoming;
+use mime_guess::mime::Mime;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::fs::Metadata;
+use std::future;
+use std::hash::BuildHasher;
+use std::io::{self, SeekFrom};
+use std::iter::FromIterator;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+// size of the chunks files are read in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Range requests for more ranges are answered with the whole file
+const MAX_RANGES: usize = 16;
+
+// smaller files are not worth compressing while sending them
+#[cfg(feature = "compression")]
+const MIN_COMPRESS_SIZE: u64 = 1024;
+
+/// Symlinks is the policy for symbolic links below the root directory of the
+/// served files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Symlinks {
+    /// Follow symbolic links wherever they point to.
+    Follow,
+    /// Follow symbolic links as long as they point into the root directory.
+    #[default]
+    WithinRoot,
+    /// Serve no file reached through a symbolic link below the root
+    /// directory. The root itself may be a symbolic link.
+    Deny,
+}
+
+/// Resolve returns the path of the file requested by `filepath` below the
+/// root directory, e.g. the value of a catch-all parameter like "/css/app.css".
+///
+/// Paths containing ".." segments, backslashes, NUL bytes or components which
+/// are not plain file names, like the drive prefixes of Windows, are rejected
+/// with `io::ErrorKind::InvalidInput`. Files reached through symbolic links
+/// against the policy are rejected with `io::ErrorKind::PermissionDenied`.
+/// With `Symlinks::WithinRoot` the returned path is canonical.
+pub async fn resolve(root: &Path, filepath: &str, symlinks: Symlinks) -> io::Result<PathBuf> {
+    let mut path = root.to_path_buf();
+    for segment in filepath.split('/') {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        if segment == ".." || segment.contains(['\\', '\0']) {
+            return Err(invalid_path(filepath));
+        }
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) => path.push(name),
+            _ => return Err(invalid_path(filepath)),
+        }
+    }
+
+    match symlinks {
+        Symlinks::Follow => Ok(path),
+        Symlinks::WithinRoot => {
+            let root = tokio::fs::canonicalize(root).await?;
+            let path = tokio::fs::canonicalize(&path).await?;
+            if !path.starts_with(&root) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "symbolic link pointing out of the root",
+                ));
+            }
+            Ok(path)
+        }
+        Symlinks::Deny => {
+            let mut parent = root.to_path_buf();
+            for name in path.strip_prefix(root).unwrap().components() {
+                parent.push(name);
+                if tokio::fs::symlink_metadata(&parent)
+                    .await?
+                    .file_type()
+                    .is_symlink()
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "symbolic links are denied",
+                    ));
+                }
+            }
+            Ok(path)
+        }
+    }
+}
+
+fn invalid_path(filepath: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid file path '{}'", filepath),
+    )
+}
+
+/// Listing is the format of the directory listings of a `ServeDir`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Listing {
+    /// An HTML page linking the entries.
+    Html,
+    /// A JSON array of the entries, with their name, whether they are a
+    /// directory, their size and their modification time.
+    Json,
+}
+
+/// ServeDir serves the files below a root directory, see `Router::serve_dir`.
+///
+/// By default, requests for a directory are answered with its index.html,
+/// hidden files are not served, symbolic links are followed as long as they
+/// point into the root, and missing files are answered with 404 Not Found.
+///
+/// ```rust
+/// use radix_router::fs::{Listing, ServeDir};
+/// use radix_router::router::{Handler, Router};
+///
+/// let mut router: Router<Handler> = Router::new();
+/// router.serve_dir("/app/*filepath", ServeDir::new("dist").fallback("index.html"));
+/// router.serve_dir("/downloads/*filepath", ServeDir::new("/srv/downloads").listing(Listing::Html));
+/// ```
+pub struct ServeDir<B = Incoming> {
+    root: PathBuf,
+    index: Option<String>,
+    listing: Option<Listing>,
+    hidden: bool,
+    symlinks: Symlinks,
+    fallback: Option<PathBuf>,
+    not_found: Option<Handler<B>>,
+    precompressed: bool,
+    #[cfg(feature = "compression")]
+    compress: bool,
+}
+
+impl<B> ServeDir<B> {
+    /// New returns a ServeDir serving the files below the root.
+    pub fn new<P: Into<PathBuf>>(root: P) -> ServeDir<B> {
+        ServeDir {
+            root: root.into(),
+            index: Some("index.html".to_string()),
+            listing: None,
+            hidden: false,
+            symlinks: Symlinks::default(),
+            fallback: None,
+            not_found: None,
+            precompressed: true,
+            #[cfg(feature = "compression")]
+            compress: false,
+        }
+    }
+
+    /// Sets the name of the file served for requests of a directory,
+    /// "index.html" by default.
+    pub fn index_file<S: Into<String>>(mut self, name: S) -> Self {
+        self.index = Some(name.into());
+        self
+    }
+
+    /// Serves no index file for requests of a directory.
+    pub fn no_index_file(mut self) -> Self {
+        self.index = None;
+        self
+    }
+
+    /// Lists the entries of directories without an index file. Hidden
+    /// entries are only listed if hidden files are served.
+    pub fn listing(mut self, listing: Listing) -> Self {
+        self.listing = Some(listing);
+        self
+    }
+
+    /// Serves the files and directories whose names start with a '.', like
+    /// ".env" or ".git". Requests for them are answered like missing files by
+    /// default.
+    pub fn hidden_files(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    /// Sets the policy for symbolic links below the root.
+    pub fn symlinks(mut self, symlinks: Symlinks) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Serves the file at the path, relative to the root, for every missing
+    /// file, as the client-side routing of single page applications expects.
+    /// The NotFound handler is not called then.
+    pub fn fallback<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.fallback = Some(path.into());
+        self
+    }
+
+    /// Calls the handler for missing files instead of answering them with 404
+    /// Not Found.
+    pub fn not_found(mut self, handle: Handler<B>) -> Self {
+        self.not_found = Some(handle);
+        self
+    }
+
+    /// Serves the ".br" or ".gz" sibling of a file instead of the file, if it
+    /// exists and the client accepts its encoding. Enabled by default.
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+
+    /// Compresses text files, JavaScript, JSON, XML and WebAssembly with gzip
+    /// while sending them, if the client accepts it and there is no
+    /// precompressed sibling. Range requests are answered with the whole
+    /// compressed file then. Disabled by default.
+    #[cfg(feature = "compression")]
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+}
+
+impl<B: Send + 'static> ServeDir<B> {
+    /// Serve answers the request for the file at `filepath` below the root,
+    /// e.g. the raw value of a catch-all parameter like "/css/app%20v2.css".
+    /// `filepath` is percent-decoded before it is resolved.
+    pub async fn serve(&self, req: Request<B>, filepath: &str) -> Result<Response<Body>, BoxError> {
+        let filepath = unescape_path(filepath);
+        let path = match resolve(&self.root, &filepath, self.symlinks).await {
+            Ok(path) => path,
+            Err(err) if is_missing(&err) => return self.missing(req).await,
+            Err(err) => return Ok(io_error(&err)),
+        };
+        if !self.hidden
+            && filepath
+                .split('/')
+                .any(|segment| segment.starts_with('.') && segment != ".")
+        {
+            return self.missing(req).await;
+        }
+
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(err) if is_missing(&err) => return self.missing(req).await,
+            Err(err) => return Ok(io_error(&err)),
+        };
+        if !metadata.is_dir() {
+            return Ok(self.send(req.map(drop), path).await);
+        }
+
+        let mut index = None;
+        if let Some(name) = &self.index {
+            let path = path.join(name);
+            if tokio::fs::metadata(&path)
+                .await
+                .is_ok_and(|metadata| metadata.is_file())
+            {
+                index = Some(path);
+            }
+        }
+        if index.is_none() && self.listing.is_none() {
+            return self.missing(req).await;
+        }
+
+        // relative links of the index and the listing need the trailing slash
+        let uri = original_uri(&req);
+        if !uri.path().ends_with('/') {
+            return Ok(redirect_dir(&req));
+        }
+
+        match (index, self.listing) {
+            (Some(index), _) => Ok(self.send(req.map(drop), index).await),
+            (None, Some(listing)) => {
+                let head = req.method() == Method::HEAD;
+                Ok(list(uri.path().to_string(), head, &path, listing, self.hidden).await)
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+
+    async fn missing(&self, req: Request<B>) -> Result<Response<Body>, BoxError> {
+        if let Some(fallback) = &self.fallback {
+            return Ok(self.send(req.map(drop), self.root.join(fallback)).await);
+        }
+
+        match &self.not_found {
+            Some(not_found) => not_found.handle(req, Params::new()).await,
+            None => Ok(io_error(&io::ErrorKind::NotFound.into())),
+        }
+    }
+
+    /// Sends the file, or its representation in the encoding preferred by
+    /// the client.
+    async fn send(&self, req: Request<()>, path: PathBuf) -> Response<Body> {
+        let mut variant = Variant::identity(&path);
+        #[cfg(feature = "compression")]
+        let compress = self.compress && compressible(&variant.content_type);
+        #[cfg(not(feature = "compression"))]
+        let compress = false;
+        if !self.precompressed && !compress {
+            return send_file(req, &path, variant).await;
+        }
+
+        variant.vary = true;
+        let encodings = accepted_encodings(req.headers());
+        if self.precompressed {
+            for &encoding in &encodings {
+                let mut name = path.clone().into_os_string();
+                name.push(".");
+                name.push(encoding.extension());
+                let compressed = PathBuf::from(name);
+                // a precompressed file is never reached through a link,
+                // unless any link may be followed
+                let metadata = match self.symlinks {
+                    Symlinks::Follow => tokio::fs::metadata(&compressed).await,
+                    _ => tokio::fs::symlink_metadata(&compressed).await,
+                };
+                if metadata.is_ok_and(|metadata| metadata.is_file()) {
+                    variant.encoding = Some(encoding);
+                    return send_file(req, &compressed, variant).await;
+                }
+            }
+        }
+
+        variant.compress = compress && encodings.contains(&Encoding::Gzip);
+        send_file(req, &path, variant).await
+    }
+}
+
+impl<B: Send + 'static> Router<Handler<B>> {
+    /// ServeFiles serves files from the given file system root.
+    ///
+    /// The path must end with "/*filepath", files are then served from the local
+    /// path /defined/root/dir/*filepath.
+    ///
+    /// For example if root is "/etc" and *filepath is "passwd", the local file
+    /// "/etc/passwd" would be served.
+    ///
+    /// Files are streamed for GET and HEAD requests, supporting conditional
+    /// and Range requests, see `serve_file`. Requests for a directory are
+    /// answered with its index.html, hidden files are not served, see
+    /// `ServeDir` for the other options.
+    ///
+    /// The requested path is percent-decoded, e.g. "/a%20b.txt" serves the
+    /// file "a b.txt", whether `decode_params` is enabled or not.
+    ///
+    /// Requested paths can not escape the root: paths with ".." segments,
+    /// backslashes or NUL bytes are answered with 400 Bad Request, and symbolic
+    /// links pointing out of the root with 403 Forbidden, see `resolve`.
+    ///
+    /// ```rust
+    /// extern crate radix_router;
+    /// use radix_router::router::{Router, Handler};
+    /// let mut router: Router<Handler> = Router::new();
+    /// router.serve_files("/examples/*filepath", "examples");
+    /// ```
+    pub fn serve_files<P: Into<PathBuf>>(&mut self, path: &str, root: P) {
+        self.serve_dir(path, ServeDir::new(root));
+    }
+
+    /// ServeDir serves the files below the root of the `ServeDir` like
+    /// `serve_files`, with its options.
+    ///
+    /// ```rust
+    /// use radix_router::fs::{ServeDir, Symlinks};
+    /// use radix_router::router::{Router, Handler};
+    /// let mut router: Router<Handler> = Router::new();
+    /// let examples = ServeDir::new("examples")
+    ///     .index_file("send_file_index.html")
+    ///     .symlinks(Symlinks::Deny);
+    /// router.serve_dir("/examples/*filepath", examples);
+    /// ```
+    pub fn serve_dir(&mut self, path: &str, dir: ServeDir<B>) {
+        check_filepath(path);
+        let dir = Arc::new(dir);
+        let get_files = move |req: Request<B>, ps: Params| {
+            let dir = dir.clone();
+            // served paths are decoded once, whether decode_params is set or not
+            let filepath = ps.raw_by_name("filepath").unwrap().to_string();
+            async move { dir.serve(req, &filepath).await }
+        };
+
+        self.get(path, Box::new(get_files.clone()));
+        self.head(path, Box::new(get_files));
+    }
+}
+
+impl<B> Router<Handler<B>> {
+    /// ServeEmbedded serves assets compiled into the binary like `serve_files`
+    /// serves files, including conditional and Range requests.
+    ///
+    /// Requests for a directory are answered with its index.html asset, and
+    /// the assets with ".br" and ".gz" appended to the path of an asset are
+    /// served instead of it to clients accepting their encoding.
+    ///
+    /// ```rust
+    /// use radix_router::fs::Asset;
+    /// use radix_router::router::{Router, Handler};
+    ///
+    /// static ASSETS: &[(&str, Asset)] = &[
+    ///     ("index.html", Asset::new(b"<h1>Hello</h1>")),
+    ///     ("css/app.css", Asset::new(b"h1 { color: red }").modified(1_700_000_000)),
+    /// ];
+    ///
+    /// let mut router: Router<Handler> = Router::new();
+    /// router.serve_embedded("/static/*filepath", ASSETS);
+    /// ```
+    pub fn serve_embedded<A: Into<Assets>>(&mut self, path: &str, assets: A) {
+        check_filepath(path);
+        let assets = Arc::new(assets.into());
+        let get_assets = move |req: Request<B>, ps: Params| {
+            let res = assets.serve(req.map(drop), ps.raw_by_name("filepath").unwrap());
+            future::ready(Ok::<_, Infallible>(res))
+        };
+
+        self.get(path, Box::new(get_assets.clone()));
+        self.head(path, Box::new(get_assets));
+    }
+}
+
+fn check_filepath(path: &str) {
+    if !path.ends_with("/*filepath") {
+        panic!("path must end with /*filepath in path '{}'", path);
+    }
+}
+
+/// Asset is a file compiled into the binary, see `Router::serve_embedded`.
+///
+/// Its ETag is a hash of its data, which is computed at compile time for
+/// constant and static assets:
+///
+/// ```rust
+/// use radix_router::fs::Asset;
+///
+/// static LOGO: Asset = Asset::new(include_bytes!("../examples/send_file_index.html"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Asset {
+    data: &'static [u8],
+    hash: u64,
+    etag: Option<&'static str>,
+    modified: Option<u64>,
+}
+
+impl Asset {
+    /// New returns the asset holding the data.
+    pub const fn new(data: &'static [u8]) -> Asset {
+        // FNV-1a
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut i = 0;
+        while i < data.len() {
+            hash ^= data[i] as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+            i += 1;
+        }
+
+        Asset {
+            data,
+            hash,
+            etag: None,
+            modified: None,
+        }
+    }
+
+    /// WithEtag returns the asset holding the data, with a strong ETag like
+    /// "\"v1.2.3\"" computed by a build script. It saves hashing large
+    /// assets at compile time.
+    pub const fn with_etag(data: &'static [u8], etag: &'static str) -> Asset {
+        Asset {
+            data,
+            hash: 0,
+            etag: Some(etag),
+            modified: None,
+        }
+    }
+
+    /// Sets the modification time sent as Last-Modified, in seconds since the
+    /// Unix epoch, e.g. the time of the build.
+    pub const fn modified(self, secs: u64) -> Asset {
+        Asset {
+            modified: Some(secs),
+            ..self
+        }
+    }
+
+    /// Returns the data of the asset.
+    pub const fn data(&self) -> &'static [u8] {
+        self.data
+    }
+
+    /// Returns the ETag of the asset.
+    pub fn etag(&self) -> String {
+        match self.etag {
+            Some(etag) => etag.to_string(),
+            None => format!("\"{:016x}-{:x}\"", self.hash, self.data.len()),
+        }
+    }
+}
+
+/// Assets map paths below the served directory, like "css/app.css", to the
+/// assets compiled into the binary.
+#[derive(Debug, Clone, Default)]
+pub struct Assets {
+    assets: HashMap<String, Asset>,
+}
+
+impl Assets {
+    pub fn new() -> Assets {
+        Assets::default()
+    }
+
+    /// Adds the asset at the path, returning the asset it replaces. A leading
+    /// '/' of the path is ignored.
+    pub fn insert(&mut self, path: &str, asset: Asset) -> Option<Asset> {
+        self.assets
+            .insert(path.trim_start_matches('/').to_string(), asset)
+    }
+
+    /// Returns the asset at the path. A leading '/' of the path is ignored.
+    pub fn get(&self, path: &str) -> Option<&Asset> {
+        self.assets.get(path.trim_start_matches('/'))
+    }
+
+    /// Serve answers the request for the asset at `filepath`, e.g. the raw
+    /// value of a catch-all parameter like "/css/my%20app.css". `filepath` is
+    /// percent-decoded before it is looked up.
+    pub fn serve(&self, req: Request<()>, filepath: &str) -> Response<Body> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return method_not_allowed();
+        }
+
+        let mut path = unescape_path(filepath).trim_start_matches('/').to_string();
+        if path.is_empty() || path.ends_with('/') {
+            path += "index.html";
+        } else if self.get(&path).is_none() && self.get(&(path.clone() + "/index.html")).is_some() {
+            return redirect_dir(&req);
+        }
+        let mut asset = match self.get(&path) {
+            Some(asset) => asset,
+            None => return io_error(&io::ErrorKind::NotFound.into()),
+        };
+
+        let mut variant = Variant::identity(Path::new(&path));
+        let compressed =
+            |encoding: Encoding| self.get(&format!("{}.{}", path, encoding.extension()));
+        if compressed(Encoding::Brotli).is_some() || compressed(Encoding::Gzip).is_some() {
+            variant.vary = true;
+            if let Some((encoding, compressed)) = accepted_encodings(req.headers())
+                .into_iter()
+                .find_map(|encoding| compressed(encoding).map(|asset| (encoding, asset)))
+            {
+                variant.encoding = Some(encoding);
+                asset = compressed;
+            }
+        }
+
+        let data = Bytes::from_static(asset.data);
+        let modified = asset
+            .modified
+            .map(|secs| HttpDate::from(UNIX_EPOCH + Duration::from_secs(secs)));
+        respond(
+            &req,
+            Source::Memory(data),
+            asset.data.len() as u64,
+            Some(asset.etag()),
+            modified,
+            variant,
+        )
+    }
+}
+
+impl<'a> FromIterator<(&'a str, Asset)> for Assets {
+    fn from_iter<I: IntoIterator<Item = (&'a str, Asset)>>(iter: I) -> Assets {
+        let mut assets = Assets::new();
+        for (path, asset) in iter {
+            assets.insert(path, asset);
+        }
+        assets
+    }
+}
+
+impl<'a> From<&'a [(&'a str, Asset)]> for Assets {
+    fn from(assets: &'a [(&'a str, Asset)]) -> Assets {
+        assets.iter().copied().collect()
+    }
+}
+
+/// Returns the URI requested by the client, before the prefix of a mounted
+/// router was stripped.
+fn original_uri<B>(req: &Request<B>) -> &http::Uri {
+    req.extensions()
+        .get::<OriginalUri>()
+        .map_or(req.uri(), |uri| &uri.0)
+}
+
+/// Redirects the request for a directory to the path with a trailing slash,
+/// following the redirect policy of the router.
+fn redirect_dir<B>(req: &Request<B>) -> Response<Body> {
+    let redirects = req
+        .extensions()
+        .get::<Redirects>()
+        .copied()
+        .unwrap_or_default();
+    redirects.redirect(req, &(original_uri(req).path().to_string() + "/"))
+}
+
+fn method_not_allowed() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::METHOD_NOT_ALLOWED)
+        .header(header::ALLOW, "GET, HEAD")
+        .body(full("METHOD_NOT_ALLOWED"))
+        .unwrap()
+}
+
+/// Encoding is a content coding of precompressed and compressed files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+}
+
+/// Returns the encodings accepted by the Accept-Encoding header, preferred
+/// first. Brotli is preferred over gzip of the same quality.
+fn accepted_encodings(headers: &HeaderMap) -> Vec<Encoding> {
+    let (mut brotli, mut gzip, mut any) = (None, None, None);
+    let values = headers.get_all(header::ACCEPT_ENCODING).iter();
+    for item in values
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or_default().trim();
+        let quality = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1.0), |(_, q)| q.trim().parse::<f32>().ok())
+            .filter(|q| (0.0..=1.0).contains(q));
+
+        if coding.eq_ignore_ascii_case("br") {
+            brotli = quality;
+        } else if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+            gzip = quality;
+        } else if coding == "*" {
+            any = quality;
+        }
+    }
+
+    let mut encodings: Vec<(Encoding, f32)> = [
+        (Encoding::Brotli, brotli.or(any)),
+        (Encoding::Gzip, gzip.or(any)),
+    ]
+    .iter()
+    .filter_map(|&(encoding, quality)| quality.filter(|q| *q > 0.0).map(|q| (encoding, q)))
+    .collect();
+    encodings.sort_by(|a, b| b.1.total_cmp(&a.1));
+    encodings
+        .into_iter()
+        .map(|(encoding, _)| encoding)
+        .collect()
+}
+
+/// Reports whether files of the type are worth compressing.
+#[cfg(feature = "compression")]
+fn compressible(content_type: &Mime) -> bool {
+    use mime_guess::mime;
+
+    content_type.type_() == mime::TEXT
+        || content_type
+            .suffix()
+            .is_some_and(|suffix| suffix == mime::XML || suffix == mime::JSON)
+        || (content_type.type_() == mime::APPLICATION
+            && matches!(
+                content_type.subtype().as_str(),
+                "javascript" | "json" | "xml" | "wasm"
+            ))
+}
+
+fn is_missing(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+    )
+}
+
+struct Entry {
+    name: String,
+    dir: bool,
+    size: u64,
+    modified: Option<HttpDate>,
+}
+
+/// Answers the request for a directory with the listing of its entries.
+async fn list(
+    path: String,
+    head: bool,
+    dir: &Path,
+    listing: Listing,
+    hidden: bool,
+) -> Response<Body> {
+    let mut read_dir = match tokio::fs::read_dir(dir).await {
+        Ok(read_dir) => read_dir,
+        Err(err) => return io_error(&err),
+    };
+
+    let mut entries = Vec::new();
+    loop {
+        let entry = match read_dir.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(err) => return io_error(&err),
+        };
+        // names which are not valid UTF-8 can not be linked
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if !hidden && name.starts_with('.') {
+            continue;
+        }
+        // skips broken symbolic links
+        let metadata = match tokio::fs::metadata(entry.path()).await {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        entries.push(Entry {
+            name,
+            dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(HttpDate::from),
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let (content_type, body) = match listing {
+        Listing::Html => ("text/html; charset=utf-8", html_listing(&path, &entries)),
+        Listing::Json => ("application/json", json_listing(&entries)),
+    };
+
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, body.len());
+    if head {
+        response.body(empty()).unwrap()
+    } else {
+        response.body(full(body)).unwrap()
+    }
+}
+
+fn html_listing(path: &str, entries: &[Entry]) -> String {
+    let title = format!("Index of {}", escape_html(path));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n<ul>\n",
+        title
+    );
+    if path != "/" {
+        html += "<li><a href=\"../\">../</a></li>\n";
+    }
+    for entry in entries {
+        let slash = if entry.dir { "/" } else { "" };
+        // "./" keeps names like "a:b" from being taken for a scheme
+        html += &format!(
+            "<li><a href=\"./{}{}\">{}{}</a></li>\n",
+            escape_html(&escape_path(&entry.name, false)),
+            slash,
+            escape_html(&entry.name),
+            slash
+        );
+    }
+    html += "</ul>\n</body>\n</html>\n";
+    html
+}
+
+fn json_listing(entries: &[Entry]) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            let modified = match entry.modified {
+                Some(modified) => json_string(&modified.to_string()),
+                None => "null".to_string(),
+            };
+            format!(
+                "{{\"name\":{},\"dir\":{},\"size\":{},\"modified\":{}}}",
+                json_string(&entry.name),
+                entry.dir,
+                entry.size,
+                modified
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            c if (c as u32) < 0x20 => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// ServeFile answers the request with the file at the path.
+///
+/// Only GET and HEAD requests are served, the response to a HEAD request
+/// carries the headers of the GET response without a body. Missing files and
+/// directories are answered with 404 (Not Found), files which can not be read
+/// with 403 (Forbidden).
+///
+/// The request is taken without its body, see `Request::map`:
+///
+/// ```rust
+/// use http::{Request, Response};
+/// use hyper::body::Incoming;
+/// use radix_router::fs::serve_file;
+/// use radix_router::router::{Handler, Params, Router};
+/// use std::convert::Infallible;
+///
+/// let mut router: Router<Handler> = Router::new();
+/// router.get("/favicon.ico", Box::new(|req: Request<Incoming>, _: Params| {
+///     let req = req.map(drop);
+///     async { Ok::<_, Infallible>(serve_file(req, "static/favicon.ico").await) }
+/// }));
+/// ```
+pub async fn serve_file<P: AsRef<Path>>(req: Request<()>, path: P) -> Response<Body> {
+    let path = path.as_ref();
+    send_file(req, path, Variant::identity(path)).await
+}
+
+/// Variant is the representation of a file sent by `send_file`.
+struct Variant {
+    content_type: Mime,
+    // the encoding of a precompressed file
+    encoding: Option<Encoding>,
+    // whether the representation depends on the Accept-Encoding
+    vary: bool,
+    // whether the file is compressed with gzip while sending it
+    compress: bool,
+}
+
+impl Variant {
+    fn identity(path: &Path) -> Variant {
+        Variant {
+            content_type: mime_guess::from_path(path).first_or_octet_stream(),
+            encoding: None,
+            vary: false,
+            compress: false,
+        }
+    }
+}
+
+async fn send_file(req: Request<()>, path: &Path, variant: Variant) -> Response<Body> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return method_not_allowed();
+    }
+
+    let file = match File::open(path).await {
+        Ok(file) => file,
+        Err(err) => return io_error(&err),
+    };
+    let metadata = match file.metadata().await {
+        Ok(metadata) => metadata,
+        Err(err) => return io_error(&err),
+    };
+    if !metadata.is_file() {
+        return io_error(&io::ErrorKind::NotFound.into());
+    }
+
+    let modified = metadata.modified().ok();
+    let etag = modified.map(|modified| etag(&metadata, modified));
+    let modified = modified.map(HttpDate::from);
+    respond(
+        &req,
+        Source::File(file),
+        metadata.len(),
+        etag,
+        modified,
+        variant,
+    )
+}
+
+/// Respond answers a GET or HEAD request with the variant of a file of `len`
+/// bytes read from the source, evaluating the conditional and Range headers.
+fn respond(
+    req: &Request<()>,
+    source: Source,
+    len: u64,
+    mut etag: Option<String>,
+    modified: Option<HttpDate>,
+    variant: Variant,
+) -> Response<Body> {
+    #[cfg(feature = "compression")]
+    let variant = Variant {
+        compress: variant.compress && len >= MIN_COMPRESS_SIZE,
+        ..variant
+    };
+
+    let mut response = Response::builder();
+    if variant.compress {
+        // the compressed bytes are not guaranteed to stay the same
+        etag = etag.map(|etag| format!("W/{}-gzip\"", &etag[..etag.len() - 1]));
+    } else {
+        response = response.header(header::ACCEPT_RANGES, "bytes");
+    }
+    if variant.vary {
+        response = response.header(header::VARY, "Accept-Encoding");
+    }
+    if let Some(modified) = modified {
+        response = response.header(header::LAST_MODIFIED, modified.to_string());
+    }
+    if let Some(etag) = &etag {
+        response = response.header(header::ETAG, etag.as_str());
+    }
+
+    if let Some(status) = preconditions(req.headers(), etag.as_deref(), modified) {
+        return response.status(status).body(empty()).unwrap();
+    }
+
+    let content_type = &variant.content_type;
+    let ranges = match req.headers().get(header::RANGE) {
+        Some(range) if !variant.compress && if_range(req.headers(), etag.as_deref(), modified) => {
+            parse_range(range, len)
+        }
+        _ => Ranges::Full,
+    };
+    if let Some(encoding) = variant.encoding {
+        response = response.header(header::CONTENT_ENCODING, encoding.name());
+    } else if variant.compress {
+        response = response.header(header::CONTENT_ENCODING, Encoding::Gzip.name());
+    }
+
+    let mut parts = VecDeque::new();
+    match ranges {
+        Ranges::Full => {
+            response = response.header(header::CONTENT_TYPE, content_type.as_ref());
+            parts.push_back(Part::File(0..len));
+        }
+        Ranges::Unsatisfiable => {
+            return response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                .body(empty())
+                .unwrap();
+        }
+        Ranges::Partial(ranges) if ranges.len() == 1 => {
+            let range = &ranges[0];
+            response = response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, content_type.as_ref())
+                .header(header::CONTENT_RANGE, content_range(range, len));
+            parts.push_back(Part::File(range.clone()));
+        }
+        Ranges::Partial(ranges) => {
+            let boundary = boundary();
+            response = response.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_TYPE,
+                format!("multipart/byteranges; boundary={}", boundary),
+            );
+            for (i, range) in ranges.into_iter().enumerate() {
+                let head = format!(
+                    "{}--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                    if i == 0 { "" } else { "\r\n" },
+                    boundary,
+                    content_type,
+                    content_range(&range, len),
+                );
+                parts.push_back(Part::Bytes(head.into()));
+                parts.push_back(Part::File(range));
+            }
+            parts.push_back(Part::Bytes(format!("\r\n--{}--\r\n", boundary).into()));
+        }
+    }
+
+    let body = FileBody::new(source, parts);
+    if !variant.compress {
+        response = response.header(header::CONTENT_LENGTH, body.remaining);
+    }
+    if req.method() == Method::HEAD {
+        return response.body(empty()).unwrap();
+    }
+
+    #[cfg(feature = "compression")]
+    let body = if variant.compress {
+        boxed(GzipBody::new(body))
+    } else {
+        boxed(body)
+    };
+    #[cfg(not(feature = "compression"))]
+    let body = boxed(body);
+    response.body(body).unwrap()
+}
+
+/// Answers a failure to resolve or open a file.
+pub(crate) fn io_error(err: &io::Error) -> Response<Body> {
+    let (status, body) = match err.kind() {
+        io::ErrorKind::InvalidInput => (StatusCode::BAD_REQUEST, "BAD_REQUEST"),
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => {
+            (StatusCode::NOT_FOUND, "NOT_FOUND")
+        }
+        io::ErrorKind::PermissionDenied => (StatusCode::FORBIDDEN, "FORBIDDEN"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_SERVER_ERROR"),
+    };
+
+    Response::builder().status(status).body(full(body)).unwrap()
+}
+
+/// Returns the strong ETag of a file, built from its modification time and
+/// size.
+fn etag(metadata: &Metadata, modified: SystemTime) -> String {
+    let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "\"{:x}.{:x}-{:x}\"",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        metadata.len()
+    )
+}
+
+fn header_date(headers: &HeaderMap, name: header::HeaderName) -> Option<HttpDate> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| HttpDate::from_str(value).ok())
+}
+
+/// Reports whether an If-Match or If-None-Match header matches the ETag of the
+/// file. The weak comparison ignores the W/ prefix of weak ETags.
+fn etag_matches(value: &HeaderValue, etag: Option<&str>, weak: bool) -> bool {
+    let value = match value.to_str() {
+        Ok(value) => value.trim(),
+        Err(_) => return false,
+    };
+    // the file exists, so it has a current representation
+    if value == "*" {
+        return true;
+    }
+
+    let etag = match etag {
+        Some(etag) => etag,
+        None => return false,
+    };
+    // splits the opaque tag from the weakness
+    fn opaque(tag: &str) -> (&str, bool) {
+        match tag.strip_prefix("W/") {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        }
+    }
+
+    let (etag, etag_weak) = opaque(etag);
+    value.split(',').map(str::trim).any(|tag| {
+        let (tag, tag_weak) = opaque(tag);
+        tag == etag && (weak || !(tag_weak || etag_weak))
+    })
+}
+
+/// Evaluates the preconditions of the request in the order of RFC 9110,
+/// section 13.2.2, returning the status code answering a failed one.
+fn preconditions(
+    headers: &HeaderMap,
+    etag: Option<&str>,
+    modified: Option<HttpDate>,
+) -> Option<StatusCode> {
+    if let Some(if_match) = headers.get(header::IF_MATCH) {
+        if !etag_matches(if_match, etag, false) {
+            return Some(StatusCode::PRECONDITION_FAILED);
+        }
+    } else if let (Some(since), Some(modified)) =
+        (header_date(headers, header::IF_UNMODIFIED_SINCE), modified)
+    {
+        if modified > since {
+            return Some(StatusCode::PRECONDITION_FAILED);
+        }
+    }
+
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        if etag_matches(if_none_match, etag, true) {
+            return Some(StatusCode::NOT_MODIFIED);
+        }
+    } else if let (Some(since), Some(modified)) =
+        (header_date(headers, header::IF_MODIFIED_SINCE), modified)
+    {
+        if modified <= since {
+            return Some(StatusCode::NOT_MODIFIED);
+        }
+    }
+
+    None
+}
+
+/// Reports whether the Range header applies, which it does unless an If-Range
+/// header names another version of the file.
+fn if_range(headers: &HeaderMap, etag: Option<&str>, modified: Option<HttpDate>) -> bool {
+    let value = match headers.get(header::IF_RANGE) {
+        Some(value) => value.to_str().unwrap_or_default().trim(),
+        None => return true,
+    };
+
+    if value.starts_with('"') {
+        Some(value) == etag
+    } else if value.starts_with("W/") {
+        // If-Range requires the strong comparison
+        false
+    } else {
+        modified.is_some() && HttpDate::from_str(value).ok() == modified
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Ranges {
+    Full,
+    Partial(Vec<Range<u64>>),
+    Unsatisfiable,
+}
+
+/// Parses a Range header of a file of `len` bytes. Invalid headers, headers
+/// with too many ranges and ranges adding up to more than the file are
+/// ignored, answering with the whole file.
+fn parse_range(value: &HeaderValue, len: u64) -> Ranges {
+    let specs = match value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().strip_prefix("bytes="))
+    {
+        Some(specs) => specs,
+        None => return Ranges::Full,
+    };
+
+    let digits = |s: &str| {
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse::<u64>().ok()
+        } else {
+            None
+        }
+    };
+
+    let mut ranges = Vec::new();
+    let mut specified = false;
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        specified = true;
+        let (start, end) = match spec.split_once('-') {
+            Some(range) => range,
+            None => return Ranges::Full,
+        };
+
+        let range = if start.is_empty() {
+            // the last bytes of the file
+            match digits(end) {
+                Some(suffix) => len.saturating_sub(suffix)..len,
+                None => return Ranges::Full,
+            }
+        } else {
+            let start = match digits(start) {
+                Some(start) => start,
+                None => return Ranges::Full,
+            };
+            let end = if end.is_empty() {
+                len
+            } else {
+                match digits(end) {
+                    Some(end) if end >= start => end.saturating_add(1).min(len),
+                    _ => return Ranges::Full,
+                }
+            };
+            start..end
+        };
+
+        if range.start < range.end {
+            ranges.push(range);
+        }
+    }
+
+    if !specified || ranges.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+    // overlapping ranges must not multiply the size of the file
+    if ranges
+        .iter()
+        .map(|range| range.end - range.start)
+        .sum::<u64>()
+        > len
+    {
+        return Ranges::Full;
+    }
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    Ranges::Partial(ranges)
+}
+
+fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+/// Returns a random multipart boundary.
+fn boundary() -> String {
+    let state = RandomState::new();
+    format!(
+        "{:016x}{:016x}",
+        state.hash_one(1u8),
+        state.hash_one(SystemTime::now())
+    )
+}
+
+/// Source holds the bytes of a file.
+enum Source {
+    File(File),
+    Memory(Bytes),
+}
+
+enum Part {
+    Bytes(Bytes),
+    File(Range<u64>),
+}
+
+enum State {
+    Next,
+    Seeking(u64),
+    Reading(u64),
+}
+
+/// FileBody streams the parts of a response from the source. Ranges of a file
+/// are read in chunks after seeking to them.
+struct FileBody {
+    source: Source,
+    parts: VecDeque<Part>,
+    state: State,
+    buf: BytesMut,
+    remaining: u64,
+}
+
+impl FileBody {
+    fn new(source: Source, parts: VecDeque<Part>) -> FileBody {
+        let remaining = parts
+            .iter()
+            .map(|part| match part {
+                Part::Bytes(bytes) => bytes.len() as u64,
+                Part::File(range) => range.end - range.start,
+            })
+            .sum();
+
+        FileBody {
+            source,
+            parts,
+            state: State::Next,
+            buf: BytesMut::new(),
+            remaining,
+        }
+    }
+}
+
+impl http_body::Body for FileBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let this = &mut *self;
+        loop {
+            match this.state {
+                State::Next => match this.parts.pop_front() {
+                    None => return Poll::Ready(None),
+                    Some(Part::Bytes(bytes)) => {
+                        this.remaining -= bytes.len() as u64;
+                        return Poll::Ready(Some(Ok(Frame::data(bytes))));
+                    }
+                    Some(Part::File(range)) => match &mut this.source {
+                        Source::File(file) => {
+                            Pin::new(file).start_seek(SeekFrom::Start(range.start))?;
+                            this.state = State::Seeking(range.end - range.start);
+                        }
+                        Source::Memory(bytes) => {
+                            this.remaining -= range.end - range.start;
+                            let data = bytes.slice(range.start as usize..range.end as usize);
+                            return Poll::Ready(Some(Ok(Frame::data(data))));
+                        }
+                    },
+                },
+                State::Seeking(len) => {
+                    let file = match &mut this.source {
+                        Source::File(file) => file,
+                        Source::Memory(_) => unreachable!(),
+                    };
+                    ready!(Pin::new(file).poll_complete(cx))?;
+                    this.state = State::Reading(len);
+                }
+                State::Reading(0) => this.state = State::Next,
+                State::Reading(len) => {
+                    let n = len.min(CHUNK_SIZE as u64) as usize;
+                    this.buf.resize(n, 0);
+                    let mut buf = ReadBuf::new(&mut this.buf[..n]);
+                    let file = match &mut this.source {
+                        Source::File(file) => file,
+                        Source::Memory(_) => unreachable!(),
+                    };
+                    ready!(Pin::new(file).poll_read(cx, &mut buf))?;
+                    let read = buf.filled().len();
+                    if read == 0 {
+                        let err = io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "file truncated while reading",
+                        );
+                        return Poll::Ready(Some(Err(err)));
+                    }
+
+                    this.state = State::Reading(len - read as u64);
+                    this.remaining -= read as u64;
+                    let data = this.buf.split_to(read).freeze();
+                    return Poll::Ready(Some(Ok(Frame::data(data))));
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+/// GzipBody compresses a file body with gzip.
+#[cfg(feature = "compression")]
+struct GzipBody {
+    inner: FileBody,
+    encoder: Option<flate2::write::GzEncoder<Vec<u8>>>,
+}
+
+#[cfg(feature = "compression")]
+impl GzipBody {
+    fn new(inner: FileBody) -> GzipBody {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        GzipBody {
+            inner,
+            encoder: Some(encoder),
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+impl http_body::Body for GzipBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        use std::io::Write;
+
+        let this = &mut *self;
+        loop {
+            let encoder = match this.encoder.as_mut() {
+                Some(encoder) => encoder,
+                None => return Poll::Ready(None),
+            };
+            match ready!(Pin::new(&mut this.inner).poll_frame(cx)?) {
+                Some(frame) => {
+                    if let Ok(data) = frame.into_data() {
+                        encoder.write_all(&data)?;
+                    }
+                    // the encoder may hold back the output of small writes
+                    let compressed = std::mem::take(encoder.get_mut());
+                    if !compressed.is_empty() {
+                        return Poll::Ready(Some(Ok(Frame::data(compressed.into()))));
+                    }
+                }
+                None => {
+                    let compressed = this.encoder.take().unwrap().finish()?;
+                    return Poll::Ready(Some(Ok(Frame::data(compressed.into()))));
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs::{accepted_encodings, io_error, parse_range, resolve, serve_file};
+    use crate::fs::{Asset, Assets, Encoding, Listing, Ranges, ServeDir, Symlinks};
+    use crate::router::{full, Body, Handler, Params, Router};
+    use http::{HeaderName, HeaderValue, Method, Request, Response, StatusCode};
+    use http_body_util::BodyExt;
+    use std::convert::Infallible;
+    use std::io;
+    use std::path::Path;
+
+    async fn get(path: &Path, headers: &[(&str, &str)]) -> (Response<()>, Vec<u8>) {
+        let mut req = Request::get("/").body(()).unwrap();
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).unwrap();
+            req.headers_mut()
+                .insert(name, HeaderValue::from_str(value).unwrap());
+        }
+        let res: Response<Body> = serve_file(req, path).await;
+        let (parts, body) = res.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (Response::from_parts(parts, ()), body.to_vec())
+    }
+
+    async fn serve_dir(router: &Router<Handler<()>>, path: &str) -> (Response<()>, String) {
+        let req = Request::get(path).body(()).unwrap();
+        let (parts, body) = router.serve_http(req).await.unwrap().into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (
+            Response::from_parts(parts, ()),
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("index.html"), "index").unwrap();
+        std::fs::write(root.join(".env"), "secret").unwrap();
+        std::fs::create_dir_all(root.join("docs/a b")).unwrap();
+        std::fs::create_dir(root.join(".git")).unwrap();
+        std::fs::write(root.join(".git/config"), "secret").unwrap();
+        std::fs::write(root.join("docs/<x>&.txt"), "x").unwrap();
+        std::fs::write(root.join("docs/.hidden"), "").unwrap();
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.serve_files("/files/*filepath", root);
+        router.serve_dir(
+            "/list/*filepath",
+            ServeDir::new(root).no_index_file().listing(Listing::Html),
+        );
+        router.serve_dir(
+            "/json/*filepath",
+            ServeDir::new(root)
+                .listing(Listing::Json)
+                .hidden_files(true),
+        );
+        router.serve_dir("/app/*filepath", ServeDir::new(root).fallback("index.html"));
+        router.serve_dir(
+            "/custom/*filepath",
+            ServeDir::new(root).not_found(Box::new(|req: Request<()>, _: Params| async move {
+                Ok::<_, Infallible>(Response::new(full(format!("no {}", req.uri().path()))))
+            })),
+        );
+
+        let (res, body) = serve_dir(&router, "/files/").await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("index", body);
+        let (res, _) = serve_dir(&router, "/files/docs/").await;
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        for hidden in ["/files/.env", "/files/.git/config", "/files/docs/.hidden"] {
+            let (res, _) = serve_dir(&router, hidden).await;
+            assert_eq!(StatusCode::NOT_FOUND, res.status(), "{}", hidden);
+        }
+
+        let (res, _) = serve_dir(&router, "/list/docs?sort=name").await;
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, res.status());
+        assert_eq!("/list/docs/?sort=name", res.headers()["Location"]);
+        let (res, body) = serve_dir(&router, "/list/docs/").await;
+        assert_eq!("text/html; charset=utf-8", res.headers()["Content-Type"]);
+        assert!(body.contains("<title>Index of /list/docs/</title>"));
+        assert!(body.contains("<a href=\"../\">../</a>"));
+        assert!(body.contains("<a href=\"./%3Cx%3E&amp;.txt\">&lt;x&gt;&amp;.txt</a>"));
+        assert!(body.contains("<a href=\"./a%20b/\">a b/</a>"));
+        assert!(!body.contains("hidden"));
+        for href in ["./%3Cx%3E&.txt", "./a%20b/"] {
+            let (res, _) = serve_dir(&router, &format!("/list/docs/{}", href)).await;
+            assert_eq!(StatusCode::OK, res.status(), "{}", href);
+        }
+        let (_, body) = serve_dir(&router, "/list/").await;
+        assert!(body.contains("index.html"));
+        assert!(!body.contains(".env"));
+
+        let (res, body) = serve_dir(&router, "/json/docs/").await;
+        assert_eq!("application/json", res.headers()["Content-Type"]);
+        assert!(body.starts_with("[{\"name\":\".hidden\",\"dir\":false,\"size\":0,\"modified\":\""));
+        assert!(body.contains("{\"name\":\"a b\",\"dir\":true,"));
+        let (_, body) = serve_dir(&router, "/json/.env").await;
+        assert_eq!("secret", body);
+
+        let (res, body) = serve_dir(&router, "/app/users/42").await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("index", body);
+        let (res, _) = serve_dir(&router, "/app/../secret").await;
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        // the checks apply to the decoded path
+        for encoded in [
+            "/files/%2e%2e/secret",
+            "/files/..%2fsecret",
+            "/files/..%5Csecret",
+            "/files/index.html%00.txt",
+        ] {
+            let (res, _) = serve_dir(&router, encoded).await;
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", encoded);
+        }
+        for hidden in ["/files/%2Eenv", "/files/%2egit%2Fconfig"] {
+            let (res, _) = serve_dir(&router, hidden).await;
+            assert_eq!(StatusCode::NOT_FOUND, res.status(), "{}", hidden);
+        }
+
+        let (res, body) = serve_dir(&router, "/custom/missing.txt").await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("no /custom/missing.txt", body);
+    }
+
+    #[test]
+    fn accept_encoding() {
+        use crate::fs::Encoding::{Brotli, Gzip};
+
+        let accepted = |values: &[&str]| {
+            let mut headers = http::HeaderMap::new();
+            for value in values {
+                headers.append("accept-encoding", HeaderValue::from_str(value).unwrap());
+            }
+            accepted_encodings(&headers)
+        };
+
+        assert_eq!(Vec::<Encoding>::new(), accepted(&[]));
+        assert_eq!(Vec::<Encoding>::new(), accepted(&["identity"]));
+        assert_eq!(vec![Brotli, Gzip], accepted(&["gzip, deflate, br"]));
+        assert_eq!(vec![Gzip, Brotli], accepted(&["br;q=0.5, GZIP"]));
+        assert_eq!(vec![Gzip], accepted(&["x-gzip"]));
+        assert_eq!(vec![Brotli, Gzip], accepted(&["*"]));
+        assert_eq!(vec![Gzip], accepted(&["br;q=0, *;q=0.1"]));
+        assert_eq!(vec![Brotli], accepted(&["gzip", "br;q=1.0, gzip;q=0"]));
+        assert_eq!(vec![Gzip], accepted(&["br;q=2, gzip ; Q=0.8"]));
+        assert_eq!(vec![Gzip], accepted(&["br;q=NaN, gzip"]));
+    }
+
+    #[tokio::test]
+    async fn precompressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("app.js"), "identity").unwrap();
+        std::fs::write(root.join("app.js.br"), "brotli").unwrap();
+        std::fs::write(root.join("app.js.gz"), "gzip").unwrap();
+        std::fs::write(root.join("style.css"), "identity").unwrap();
+        std::fs::write(root.join("style.css.gz"), "gzip").unwrap();
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.serve_files("/static/*filepath", root);
+        router.serve_dir("/plain/*filepath", ServeDir::new(root).precompressed(false));
+
+        let get = |path: &str, accept: &str| {
+            let req = Request::get(path)
+                .header("accept-encoding", accept)
+                .body(())
+                .unwrap();
+            let res = router.serve_http(req);
+            async move {
+                let (parts, body) = res.await.unwrap().into_parts();
+                let body = body.collect().await.unwrap().to_bytes();
+                (
+                    Response::from_parts(parts, ()),
+                    String::from_utf8(body.to_vec()).unwrap(),
+                )
+            }
+        };
+
+        let (res, body) = get("/static/app.js", "identity").await;
+        assert_eq!("identity", body);
+        assert_eq!("Accept-Encoding", res.headers()["Vary"]);
+        assert!(!res.headers().contains_key("Content-Encoding"));
+        let content_type = res.headers()["Content-Type"].clone();
+        let etag = res.headers()["ETag"].clone();
+
+        let (res, body) = get("/static/app.js", "gzip, deflate, br").await;
+        assert_eq!("brotli", body);
+        assert_eq!("br", res.headers()["Content-Encoding"]);
+        assert_eq!("Accept-Encoding", res.headers()["Vary"]);
+        assert_eq!(content_type, res.headers()["Content-Type"]);
+        assert_eq!("6", res.headers()["Content-Length"]);
+        assert_ne!(etag, res.headers()["ETag"]);
+
+        let (res, body) = get("/static/app.js", "gzip, br;q=0.8").await;
+        assert_eq!("gzip", body);
+        assert_eq!("gzip", res.headers()["Content-Encoding"]);
+        let (_, body) = get("/static/style.css", "br, gzip").await;
+        assert_eq!("gzip", body);
+        let (_, body) = get("/static/style.css", "br").await;
+        assert_eq!("identity", body);
+
+        let (res, body) = get("/plain/app.js", "br, gzip").await;
+        assert_eq!("identity", body);
+        assert!(!res.headers().contains_key("Vary"));
+
+        let req = Request::get("/static/app.js")
+            .header("accept-encoding", "br")
+            .header("range", "bytes=0-1")
+            .body(())
+            .unwrap();
+        let res = router.serve_http(req).await.unwrap();
+        assert_eq!(StatusCode::PARTIAL_CONTENT, res.status());
+        assert_eq!("bytes 0-1/6", res.headers()["Content-Range"]);
+        assert_eq!("br", res.headers()["Content-Encoding"]);
+    }
+
+    #[tokio::test]
+    async fn embedded() {
+        const INDEX: Asset = Asset::new(include_bytes!("../examples/send_file_index.html"));
+        static ASSETS: &[(&str, Asset)] = &[
+            ("index.html", INDEX),
+            ("docs/index.html", Asset::new(b"docs")),
+            (
+                "app.js",
+                Asset::with_etag(b"identity", "\"v1\"").modified(784_111_777),
+            ),
+            ("app.js.br", Asset::with_etag(b"brotli", "\"v1-br\"")),
+            ("css/my file.css", Asset::new(b"spaced")),
+            ("css/grüße.css", Asset::new(b"unicode")),
+        ];
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.serve_embedded("/static/*filepath", ASSETS);
+
+        let get = |method: Method, path: &str, headers: &[(&str, &str)]| {
+            let mut req = Request::builder().method(method).uri(path);
+            for &(name, value) in headers {
+                req = req.header(name, value);
+            }
+            let res = router.serve_http(req.body(()).unwrap());
+            async move {
+                let (parts, body) = res.await.unwrap().into_parts();
+                let body = body.collect().await.unwrap().to_bytes();
+                (Response::from_parts(parts, ()), body)
+            }
+        };
+
+        let index = include_bytes!("../examples/send_file_index.html");
+        let (res, body) = get(Method::GET, "/static/", &[]).await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(&index[..], &body[..]);
+        assert_eq!("text/html", res.headers()["Content-Type"]);
+        assert_eq!(index.len().to_string(), res.headers()["Content-Length"]);
+        assert_eq!(INDEX.etag(), res.headers()["ETag"]);
+        assert!(!res.headers().contains_key("Last-Modified"));
+        assert!(!res.headers().contains_key("Vary"));
+
+        let (res, body) = get(Method::HEAD, "/static/index.html", &[]).await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(body.is_empty());
+        assert_eq!(index.len().to_string(), res.headers()["Content-Length"]);
+
+        let etag = INDEX.etag();
+        let (res, _) = get(
+            Method::GET,
+            "/static/index.html",
+            &[("if-none-match", &etag)],
+        )
+        .await;
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+
+        let (res, body) = get(Method::GET, "/static/app.js", &[("range", "bytes=2-4")]).await;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, res.status());
+        assert_eq!(&b"ent"[..], &body[..]);
+        assert_eq!("bytes 2-4/8", res.headers()["Content-Range"]);
+        assert_eq!("\"v1\"", res.headers()["ETag"]);
+        assert_eq!(
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            res.headers()["Last-Modified"]
+        );
+        assert_eq!("Accept-Encoding", res.headers()["Vary"]);
+
+        let since = [("if-modified-since", "Sun, 06 Nov 1994 08:49:37 GMT")];
+        let (res, _) = get(Method::GET, "/static/app.js", &since).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+
+        let (res, body) = get(
+            Method::GET,
+            "/static/app.js",
+            &[("accept-encoding", "gzip, br")],
+        )
+        .await;
+        assert_eq!(&b"brotli"[..], &body[..]);
+        assert_eq!("br", res.headers()["Content-Encoding"]);
+        assert_eq!("\"v1-br\"", res.headers()["ETag"]);
+        assert_eq!(res.headers()["Content-Type"], "text/javascript");
+
+        let (res, _) = get(Method::GET, "/static/docs?page=2", &[]).await;
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, res.status());
+        assert_eq!("/static/docs/?page=2", res.headers()["Location"]);
+        let (_, body) = get(Method::GET, "/static/docs/", &[]).await;
+        assert_eq!(&b"docs"[..], &body[..]);
+
+        let (res, body) = get(Method::GET, "/static/css/my%20file.css", &[]).await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(&b"spaced"[..], &body[..]);
+        let (_, body) = get(Method::GET, "/static/css/gr%C3%BC%C3%9Fe.css", &[]).await;
+        assert_eq!(&b"unicode"[..], &body[..]);
+        let mut decoded: Router<Handler<()>> = Router::new();
+        decoded.decode_params = true;
+        decoded.serve_embedded("/static/*filepath", ASSETS);
+        let (_, body) = serve_dir(&decoded, "/static/css/my%20file.css").await;
+        assert_eq!("spaced", body);
+
+        let (res, _) = get(Method::GET, "/static/missing.js", &[]).await;
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let assets: Assets = vec![("/a.txt", Asset::new(b"a"))].into_iter().collect();
+        assert_eq!(Some(&Asset::new(b"a")), assets.get("a.txt"));
+        assert_eq!(b"a", Asset::new(b"a").data());
+        assert_ne!(Asset::new(b"a").etag(), Asset::new(b"b").etag());
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn compress() {
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let text = "hello, world\n".repeat(10_000);
+        std::fs::write(root.join("hello.txt"), &text).unwrap();
+        std::fs::write(root.join("small.txt"), "hello").unwrap();
+        std::fs::write(root.join("image.png"), &text).unwrap();
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.serve_dir("/files/*filepath", ServeDir::new(root).compress(true));
+
+        let get = |path: &str, headers: &[(&str, &str)]| {
+            let mut req = Request::get(path).body(()).unwrap();
+            for (name, value) in headers {
+                let name = HeaderName::from_bytes(name.as_bytes()).unwrap();
+                req.headers_mut()
+                    .insert(name, HeaderValue::from_str(value).unwrap());
+            }
+            let res = router.serve_http(req);
+            async move {
+                let (parts, body) = res.await.unwrap().into_parts();
+                let body = body.collect().await.unwrap().to_bytes();
+                (Response::from_parts(parts, ()), body.to_vec())
+            }
+        };
+
+        let (res, body) = get("/files/hello.txt", &[("accept-encoding", "gzip")]).await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("gzip", res.headers()["Content-Encoding"]);
+        assert_eq!("Accept-Encoding", res.headers()["Vary"]);
+        assert!(!res.headers().contains_key("Content-Length"));
+        assert!(!res.headers().contains_key("Accept-Ranges"));
+        assert!(body.len() < text.len() / 10);
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(text, decoded);
+
+        let etag = res.headers()["ETag"].to_str().unwrap().to_string();
+        assert!(etag.starts_with("W/\"") && etag.ends_with("-gzip\""));
+        let (res, _) = get(
+            "/files/hello.txt",
+            &[("accept-encoding", "gzip"), ("if-none-match", &etag)],
+        )
+        .await;
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+
+        // ranges of the compressed file are not served
+        let (res, body) = get(
+            "/files/hello.txt",
+            &[("accept-encoding", "gzip"), ("range", "bytes=0-9")],
+        )
+        .await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("gzip", res.headers()["Content-Encoding"]);
+        assert!(body.len() > 10);
+
+        for (path, accept) in [
+            ("/files/hello.txt", "br"),
+            ("/files/small.txt", "gzip"),
+            ("/files/image.png", "gzip"),
+        ] {
+            let (res, _) = get(path, &[("accept-encoding", accept)]).await;
+            assert!(!res.headers().contains_key("Content-Encoding"), "{}", path);
+            assert!(res.headers().contains_key("Content-Length"), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn traversal() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("css")).unwrap();
+        std::fs::write(root.join("css/app.css"), "").unwrap();
+        std::fs::write(dir.path().join("secret"), "").unwrap();
+
+        for policy in [Symlinks::Follow, Symlinks::WithinRoot, Symlinks::Deny] {
+            let path = resolve(&root, "/css/app.css", policy).await.unwrap();
+            assert!(path.ends_with("root/css/app.css"));
+            let path = resolve(&root, "//css/./app.css", policy).await.unwrap();
+            assert!(path.ends_with("root/css/app.css"));
+
+            for payload in [
+                "/..",
+                "/../secret",
+                "/css/../../secret",
+                "/css/..",
+                "/..\\secret",
+                "/css\\..\\..\\secret",
+                "\\..\\secret",
+                "/secret\0.txt",
+                "/\0",
+                "/css/app.css\0.png",
+            ] {
+                let err = resolve(&root, payload, policy).await.unwrap_err();
+                assert_eq!(io::ErrorKind::InvalidInput, err.kind(), "{:?}", payload);
+            }
+        }
+
+        // absolute paths in the params stay below the root
+        let path = resolve(&root, "/etc/passwd", Symlinks::Follow)
+            .await
+            .unwrap();
+        assert_eq!(root.join("etc/passwd"), path);
+        let err = resolve(&root, "/etc/passwd", Symlinks::WithinRoot)
+            .await
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        #[cfg(windows)]
+        {
+            let err = resolve(&root, "/C:/Windows/win.ini", Symlinks::Follow)
+                .await
+                .unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        }
+
+        let err = resolve(&root, "/../secret", Symlinks::Follow)
+            .await
+            .unwrap_err();
+        assert_eq!(StatusCode::BAD_REQUEST, io_error(&err).status());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("app.css"), "").unwrap();
+        std::fs::write(dir.path().join("secret"), "").unwrap();
+        symlink(root.join("app.css"), root.join("latest.css")).unwrap();
+        symlink(dir.path().join("secret"), root.join("secret")).unwrap();
+        symlink(dir.path(), root.join("parent")).unwrap();
+        // the root itself may be a link
+        symlink(&root, dir.path().join("link")).unwrap();
+        let link = dir.path().join("link");
+
+        let path = resolve(&root, "/latest.css", Symlinks::WithinRoot)
+            .await
+            .unwrap();
+        assert!(path.ends_with("root/app.css"));
+        let path = resolve(&link, "/latest.css", Symlinks::WithinRoot)
+            .await
+            .unwrap();
+        assert!(path.ends_with("root/app.css"));
+        // links leaving the root and coming back are fine
+        assert!(resolve(&root, "/parent/root/app.css", Symlinks::WithinRoot)
+            .await
+            .is_ok());
+        for payload in ["/secret", "/parent/secret"] {
+            let err = resolve(&root, payload, Symlinks::WithinRoot)
+                .await
+                .unwrap_err();
+            assert_eq!(io::ErrorKind::PermissionDenied, err.kind(), "{:?}", payload);
+        }
+
+        assert!(resolve(&link, "/app.css", Symlinks::Deny).await.is_ok());
+        for payload in ["/latest.css", "/secret", "/parent/secret"] {
+            let err = resolve(&link, payload, Symlinks::Deny).await.unwrap_err();
+            assert_eq!(io::ErrorKind::PermissionDenied, err.kind(), "{:?}", payload);
+        }
+
+        assert!(resolve(&root, "/secret", Symlinks::Follow).await.is_ok());
+        assert!(resolve(&root, "/parent/secret", Symlinks::Follow)
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn range() {
+        let range = |value: &str| parse_range(&HeaderValue::from_str(value).unwrap(), 10);
+        let partial = |ranges: &[(u64, u64)]| {
+            Ranges::Partial(ranges.iter().map(|&(start, end)| start..end).collect())
+        };
+
+        assert_eq!(partial(&[(2, 6)]), range("bytes=2-5"));
+        assert_eq!(partial(&[(7, 10)]), range("bytes=7-"));
+        assert_eq!(partial(&[(7, 10)]), range("bytes=-3"));
+        assert_eq!(partial(&[(0, 10)]), range("bytes=-30"));
+        assert_eq!(partial(&[(8, 10)]), range("bytes=8-100"));
+        assert_eq!(partial(&[(0, 1), (8, 10)]), range("bytes=0-0, 8-"));
+        assert_eq!(partial(&[(0, 1)]), range("bytes=0-0, 20-30"));
+        assert_eq!(Ranges::Unsatisfiable, range("bytes=10-"));
+        assert_eq!(Ranges::Unsatisfiable, range("bytes=-0"));
+
+        assert_eq!(Ranges::Full, range("bytes=5-2"));
+        assert_eq!(Ranges::Full, range("bytes=+1-2"));
+        assert_eq!(Ranges::Full, range("bytes=1"));
+        assert_eq!(Ranges::Full, range("bytes="));
+        assert_eq!(Ranges::Full, range("items=0-1"));
+        assert_eq!(Ranges::Full, range("bytes=0-,0-"));
+        assert_eq!(
+            Ranges::Full,
+            range(&format!("bytes={}", vec!["0-0"; 17].join(",")))
+        );
+    }
+
+    #[tokio::test]
+    async fn serve() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello.txt");
+        std::fs::write(&path, "0123456789").unwrap();
+
+        let (res, body) = get(&path, &[]).await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("text/plain", res.headers()["Content-Type"]);
+        assert_eq!("10", res.headers()["Content-Length"]);
+        assert_eq!("bytes", res.headers()["Accept-Ranges"]);
+        assert_eq!(b"0123456789", &body[..]);
+
+        let req = Request::head("/").body(()).unwrap();
+        let res = serve_file(req, &path).await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("10", res.headers()["Content-Length"]);
+        assert!(res
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes()
+            .is_empty());
+
+        let req = Request::post("/").body(()).unwrap();
+        assert_eq!(
+            StatusCode::METHOD_NOT_ALLOWED,
+            serve_file(req, &path).await.status()
+        );
+        let (res, _) = get(&dir.path().join("missing.txt"), &[]).await;
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let (res, _) = get(&path.join("file"), &[]).await;
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let (res, _) = get(dir.path(), &[]).await;
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn conditional() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.html");
+        std::fs::write(&path, "<html></html>").unwrap();
+
+        let (res, _) = get(&path, &[]).await;
+        assert_eq!("text/html", res.headers()["Content-Type"]);
+        let etag = res.headers()["ETag"].to_str().unwrap().to_string();
+        let modified = res.headers()["Last-Modified"].to_str().unwrap().to_string();
+
+        let (res, body) = get(&path, &[("if-none-match", &etag)]).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+        assert_eq!(etag, res.headers()["ETag"]);
+        assert!(body.is_empty());
+        let weak = format!("\"other\", W/{}", etag);
+        let (res, _) = get(&path, &[("if-none-match", &weak)]).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+        let (res, _) = get(&path, &[("if-modified-since", &modified)]).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+        // If-None-Match takes precedence over If-Modified-Since
+        let (res, _) = get(
+            &path,
+            &[
+                ("if-none-match", "\"other\""),
+                ("if-modified-since", &modified),
+            ],
+        )
+        .await;
+        assert_eq!(StatusCode::OK, res.status());
+        let (res, _) = get(
+            &path,
+            &[("if-modified-since", "Thu, 01 Jan 1970 00:00:00 GMT")],
+        )
+        .await;
+        assert_eq!(StatusCode::OK, res.status());
+
+        let (res, _) = get(&path, &[("if-match", "\"other\"")]).await;
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+        let (res, _) = get(&path, &[("if-match", &format!("W/{}", etag))]).await;
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+        let (res, _) = get(&path, &[("if-match", &etag)]).await;
+        assert_eq!(StatusCode::OK, res.status());
+        let (res, _) = get(
+            &path,
+            &[("if-unmodified-since", "Thu, 01 Jan 1970 00:00:00 GMT")],
+        )
+        .await;
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+    }
+
+    #[tokio::test]
+    async fn ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, "0123456789").unwrap();
+
+        let (res, body) = get(&path, &[("range", "bytes=2-5")]).await;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, res.status());
+        assert_eq!("bytes 2-5/10", res.headers()["Content-Range"]);
+        assert_eq!("4", res.headers()["Content-Length"]);
+        assert_eq!("application/octet-stream", res.headers()["Content-Type"]);
+        assert_eq!(b"2345", &body[..]);
+
+        let (res, body) = get(&path, &[("range", "bytes=-3")]).await;
+        assert_eq!("bytes 7-9/10", res.headers()["Content-Range"]);
+        assert_eq!(b"789", &body[..]);
+
+        let (res, body) = get(&path, &[("range", "bytes=10-")]).await;
+        assert_eq!(StatusCode::RANGE_NOT_SATISFIABLE, res.status());
+        assert_eq!("bytes */10", res.headers()["Content-Range"]);
+        assert!(body.is_empty());
+
+        let (res, body) = get(&path, &[("range", "bytes=0-1,8-")]).await;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, res.status());
+        let content_type = res.headers()["Content-Type"].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let expected = format!(
+            "--{0}\r\nContent-Type: application/octet-stream\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{0}\r\nContent-Type: application/octet-stream\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--{0}--\r\n",
+            boundary
+        );
+        assert_eq!(expected, String::from_utf8(body).unwrap());
+        assert_eq!(expected.len().to_string(), res.headers()["Content-Length"]);
+
+        let (res, _) = get(&path, &[]).await;
+        let etag = res.headers()["ETag"].to_str().unwrap().to_string();
+        let modified = res.headers()["Last-Modified"].to_str().unwrap().to_string();
+        let (res, _) = get(&path, &[("range", "bytes=2-5"), ("if-range", &etag)]).await;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, res.status());
+        let (res, _) = get(&path, &[("range", "bytes=2-5"), ("if-range", &modified)]).await;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, res.status());
+        let (res, body) = get(&path, &[("range", "bytes=2-5"), ("if-range", "\"other\"")]).await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(b"0123456789", &body[..]);
+    }
+
+    #[tokio::test]
+    async fn stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.bin");
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let req = Request::get("/").body(()).unwrap();
+        let mut body = serve_file(req, &path).await.into_body();
+        let mut chunks = 0;
+        let mut streamed = Vec::new();
+        while let Some(frame) = body.frame().await {
+            streamed.extend_from_slice(&frame.unwrap().into_data().unwrap());
+            chunks += 1;
+        }
+        assert!(chunks > 1);
+        assert_eq!(data, streamed);
+
+        let (_, body) = get(&path, &[("range", "bytes=65530-131080")]).await;
+        assert_eq!(&data[65530..=131080], &body[..]);
+    }
+}
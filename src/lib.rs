@@ -5,8 +5,18 @@
 // extern crate tokio_fs;
 // extern crate tokio_io;
 
+#[cfg(feature = "serde")]
+mod de;
+pub mod fs;
+pub mod method;
+pub mod middleware;
 pub mod path;
 pub mod router;
+pub mod service;
 pub mod tree;
 
-pub use crate::router::{BoxFut, Router};
+pub use crate::router::{
+    Body, BoxError, BoxFut, Handler, HandlerError, PanicHandler, ParamError, Params, ParamsRef,
+    ResponseFuture, Router,
+};
+pub use crate::tree::{InsertError, LookupError, Match};
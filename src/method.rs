@@ -0,0 +1,220 @@
+//! A map keyed by HTTP method, used for the per-method trees of a `Router`.
+
+use http::Method;
+use std::fmt;
+
+// the standard methods, sorted by name, in the order of the slots
+static STANDARD: [Method; 9] = [
+    Method::CONNECT,
+    Method::DELETE,
+    Method::GET,
+    Method::HEAD,
+    Method::OPTIONS,
+    Method::PATCH,
+    Method::POST,
+    Method::PUT,
+    Method::TRACE,
+];
+
+/// Returns the slot of a standard method.
+fn slot(method: &Method) -> Option<usize> {
+    match *method {
+        Method::CONNECT => Some(0),
+        Method::DELETE => Some(1),
+        Method::GET => Some(2),
+        Method::HEAD => Some(3),
+        Method::OPTIONS => Some(4),
+        Method::PATCH => Some(5),
+        Method::POST => Some(6),
+        Method::PUT => Some(7),
+        Method::TRACE => Some(8),
+        _ => None,
+    }
+}
+
+/// MethodMap maps HTTP methods to values. The standard methods are looked up
+/// by index without comparing strings. Custom methods like PROPFIND or PURGE
+/// are kept in a list next to them.
+///
+/// Iteration yields the standard methods sorted by name, then the custom
+/// methods sorted by name.
+pub struct MethodMap<V> {
+    standard: [Option<V>; 9],
+    custom: Vec<(Method, V)>,
+}
+
+impl<V> Default for MethodMap<V> {
+    fn default() -> Self {
+        MethodMap::new()
+    }
+}
+
+impl<V> MethodMap<V> {
+    pub fn new() -> MethodMap<V> {
+        MethodMap {
+            standard: Default::default(),
+            custom: Vec::new(),
+        }
+    }
+
+    /// Returns the value of the method.
+    pub fn get(&self, method: &Method) -> Option<&V> {
+        match slot(method) {
+            Some(i) => self.standard[i].as_ref(),
+            None => self
+                .custom
+                .iter()
+                .find(|(custom, _)| custom == method)
+                .map(|(_, value)| value),
+        }
+    }
+
+    /// Returns the value of the method mutably.
+    pub fn get_mut(&mut self, method: &Method) -> Option<&mut V> {
+        match slot(method) {
+            Some(i) => self.standard[i].as_mut(),
+            None => self
+                .custom
+                .iter_mut()
+                .find(|(custom, _)| custom == method)
+                .map(|(_, value)| value),
+        }
+    }
+
+    /// Returns the value of the method, inserting the value returned by `f`
+    /// if there is none.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, method: Method, f: F) -> &mut V {
+        if let Some(i) = slot(&method) {
+            return self.standard[i].get_or_insert_with(f);
+        }
+
+        let i = match self
+            .custom
+            .binary_search_by(|(custom, _)| custom.as_str().cmp(method.as_str()))
+        {
+            Ok(i) => i,
+            Err(i) => {
+                self.custom.insert(i, (method, f()));
+                i
+            }
+        };
+        &mut self.custom[i].1
+    }
+
+    /// Sets the value of the method, returning the previous one.
+    pub fn insert(&mut self, method: Method, value: V) -> Option<V> {
+        let mut value = Some(value);
+        let slot = self.get_or_insert_with(method, || value.take().unwrap());
+        value.map(|value| std::mem::replace(slot, value))
+    }
+
+    /// Removes the method and returns its value.
+    pub fn remove(&mut self, method: &Method) -> Option<V> {
+        match slot(method) {
+            Some(i) => self.standard[i].take(),
+            None => {
+                let i = self
+                    .custom
+                    .iter()
+                    .position(|(custom, _)| custom == method)?;
+                Some(self.custom.remove(i).1)
+            }
+        }
+    }
+
+    /// Reports whether the map holds a value for the method.
+    pub fn contains_key(&self, method: &Method) -> bool {
+        self.get(method).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.standard.iter().flatten().count() + self.custom.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.standard.iter().all(Option::is_none) && self.custom.is_empty()
+    }
+
+    /// Returns an iterator over the methods and their values.
+    pub fn iter(&self) -> impl Iterator<Item = (&Method, &V)> {
+        STANDARD
+            .iter()
+            .zip(self.standard.iter())
+            .filter_map(|(method, value)| value.as_ref().map(|value| (method, value)))
+            .chain(self.custom.iter().map(|(method, value)| (method, value)))
+    }
+
+    /// Returns an iterator over the methods and their mutable values.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&Method, &mut V)> {
+        STANDARD
+            .iter()
+            .zip(self.standard.iter_mut())
+            .filter_map(|(method, value)| value.as_mut().map(|value| (method, value)))
+            .chain(
+                self.custom
+                    .iter_mut()
+                    .map(|(method, value)| (&*method, value)),
+            )
+    }
+
+    /// Returns an iterator over all standard methods, whether they are in the
+    /// map or not, followed by the custom methods in the map.
+    pub fn all_keys(&self) -> impl Iterator<Item = &Method> {
+        STANDARD
+            .iter()
+            .chain(self.custom.iter().map(|(method, _)| method))
+    }
+
+    /// Returns an iterator over the methods.
+    pub fn keys(&self) -> impl Iterator<Item = &Method> {
+        self.iter().map(|(method, _)| method)
+    }
+
+    /// Returns an iterator over the values.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for MethodMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::method::MethodMap;
+    use http::Method;
+
+    #[test]
+    fn method_map() {
+        let purge = Method::from_bytes(b"PURGE").unwrap();
+        let propfind = Method::from_bytes(b"PROPFIND").unwrap();
+
+        let mut map = MethodMap::new();
+        assert!(map.is_empty());
+        assert_eq!(None, map.insert(Method::POST, 1));
+        assert_eq!(None, map.insert(purge.clone(), 2));
+        assert_eq!(None, map.insert(Method::GET, 3));
+        assert_eq!(None, map.insert(propfind.clone(), 4));
+        assert_eq!(Some(1), map.insert(Method::POST, 5));
+        *map.get_or_insert_with(Method::GET, || 0) += 10;
+
+        assert_eq!(4, map.len());
+        assert_eq!(Some(&13), map.get(&Method::GET));
+        assert_eq!(Some(&2), map.get(&purge));
+        assert_eq!(None, map.get(&Method::PUT));
+        // methods are case-sensitive
+        assert_eq!(None, map.get(&Method::from_bytes(b"Get").unwrap()));
+
+        let keys: Vec<&str> = map.keys().map(Method::as_str).collect();
+        assert_eq!(vec!["GET", "POST", "PROPFIND", "PURGE"], keys);
+
+        assert_eq!(Some(2), map.remove(&purge));
+        assert_eq!(Some(13), map.remove(&Method::GET));
+        assert_eq!(None, map.remove(&Method::GET));
+        let values: Vec<i32> = map.values().cloned().collect();
+        assert_eq!(vec![5, 4], values);
+    }
+}
@@ -0,0 +1,272 @@
+//! Middleware wrapping the handlers of a `Router`.
+//!
+//! Middleware is run in a fixed order: the router-wide middleware first, then
+//! the middleware of the groups containing the request path from the outermost
+//! to the innermost, then the middleware wrapped around the route's handler
+//! with `wrap`. Each layer runs in the order it was added. Requests answered by
+//! the router itself (NotFound, MethodNotAllowed, redirects and OPTIONS) pass
+//! through the router-wide and group middleware as well.
+
+use crate::router::{ready, strip_body, Body, BoxFut, Handle, Handler, Params};
+use http::{Request, Response};
+
+/// HandlerBody names the request body type of a handle, which lets a
+/// `Router<T>` hold middleware for any `T`. It is implemented for `Handler<B>`.
+pub trait HandlerBody {
+    type Body;
+}
+
+impl<B> HandlerBody for Handler<B> {
+    type Body = B;
+}
+
+/// Middleware sees every request before the handler. It can pass the request
+/// on with `next.run(req, ps)` and post-process the returned future, or
+/// short-circuit by returning its own response.
+///
+/// Any `Fn(Request<B>, Params, Next<B>) -> BoxFut` is a middleware for a
+/// `Router<Handler<B>>`.
+///
+/// ```rust
+/// use hyper::body::Incoming;
+/// use http::{Request, Response, StatusCode};
+/// use radix_router::middleware::Next;
+/// use radix_router::router::{empty, BoxFut, Handler, Params, Router};
+///
+/// let mut router: Router<Handler> = Router::new();
+/// router.add_middleware(|req: Request<Incoming>, ps: Params, next: Next| -> BoxFut {
+///     if req.headers().contains_key("authorization") {
+///         next.run(req, ps)
+///     } else {
+///         let mut res = Response::new(empty());
+///         *res.status_mut() = StatusCode::UNAUTHORIZED;
+///         Box::pin(async { Ok(res) })
+///     }
+/// });
+/// ```
+pub trait Middleware<T>: Send + Sync {
+    fn handle(&self, req: Request<T::Body>, ps: Params, next: Next<'_, T::Body>) -> BoxFut
+    where
+        T: HandlerBody;
+}
+
+impl<F, B> Middleware<Handler<B>> for F
+where
+    F: Fn(Request<B>, Params, Next<'_, B>) -> BoxFut + Send + Sync,
+{
+    fn handle(
+        &self,
+        req: Request<<Handler<B> as HandlerBody>::Body>,
+        ps: Params,
+        next: Next<'_, <Handler<B> as HandlerBody>::Body>,
+    ) -> BoxFut {
+        (*self)(req, ps, next)
+    }
+}
+
+/// Next runs the rest of the middleware chain and then the handler.
+pub struct Next<'a, B = hyper::body::Incoming> {
+    middleware: &'a [&'a dyn Middleware<Handler<B>>],
+    endpoint: Endpoint<'a, B>,
+}
+
+/// Endpoint is what a request reaches after the middleware: a handler, or a
+/// response built by the router.
+pub(crate) enum Endpoint<'a, B> {
+    Handle(&'a dyn Handle<B>),
+    // a GET handler answering a HEAD request, see Router::handle_head
+    Head(&'a dyn Handle<B>),
+    Response(Response<Body>),
+}
+
+impl<'a, B> Next<'a, B> {
+    pub(crate) fn new(
+        middleware: &'a [&'a dyn Middleware<Handler<B>>],
+        endpoint: Endpoint<'a, B>,
+    ) -> Next<'a, B> {
+        Next {
+            middleware,
+            endpoint,
+        }
+    }
+
+    /// Run passes the request to the next middleware, or to the handler.
+    pub fn run(self, req: Request<B>, ps: Params) -> BoxFut {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => middleware.handle(req, ps, Next::new(rest, self.endpoint)),
+            None => match self.endpoint {
+                Endpoint::Handle(handle) => handle.handle(req, ps),
+                Endpoint::Head(handle) => strip_body(handle.handle(req, ps)),
+                Endpoint::Response(response) => ready(response),
+            },
+        }
+    }
+}
+
+/// Wrapped is a handler wrapped in a middleware, see `wrap`.
+pub struct Wrapped<B, M> {
+    middleware: M,
+    handle: Handler<B>,
+}
+
+impl<B, M> Handle<B> for Wrapped<B, M>
+where
+    M: Middleware<Handler<B>>,
+{
+    fn handle(&self, req: Request<B>, ps: Params) -> BoxFut {
+        let next = Next::new(&[], Endpoint::Handle(&*self.handle));
+        self.middleware.handle(req, ps, next)
+    }
+}
+
+/// Wrap returns a handler running the middleware in front of the handle, to
+/// add middleware to a single route. The middleware runs after the router-wide
+/// and group middleware; wrapping a wrapped handler runs the outer middleware
+/// first.
+///
+/// ```rust
+/// use hyper::body::Incoming;
+/// use http::{Request, Response};
+/// use radix_router::middleware::{wrap, Next};
+/// use radix_router::router::{full, BoxFut, Handler, Params, Router};
+/// use std::convert::Infallible;
+///
+/// let mut router: Router<Handler> = Router::new();
+/// let show_user: Handler = Box::new(|_: Request<Incoming>, _: Params| async {
+///     Ok::<_, Infallible>(Response::new(full("user")))
+/// });
+/// let log = |req: Request<Incoming>, ps: Params, next: Next| -> BoxFut {
+///     println!("{} {}", req.method(), req.uri());
+///     next.run(req, ps)
+/// };
+/// router.get("/users/:id", wrap(log, show_user));
+/// ```
+pub fn wrap<B, M>(middleware: M, handle: Handler<B>) -> Handler<B>
+where
+    B: 'static,
+    M: Middleware<Handler<B>> + 'static,
+{
+    Box::new(Wrapped { middleware, handle })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::middleware::{wrap, Next};
+    use crate::router::{empty, full, Body, BoxFut, Handler, Params, Router};
+    use http::{HeaderValue, Request, Response, StatusCode};
+    use http_body_util::BodyExt;
+    use std::convert::Infallible;
+
+    // appends its name to the x-trace header of the request, and to the
+    // x-trace header of the response on the way back
+    fn trace(name: &'static str) -> impl Fn(Request<()>, Params, Next<()>) -> BoxFut {
+        move |mut req, ps, next| {
+            let trace = match req.headers().get("x-trace") {
+                Some(trace) => format!("{} {}", trace.to_str().unwrap(), name),
+                None => name.to_string(),
+            };
+            req.headers_mut()
+                .insert("x-trace", HeaderValue::from_str(&trace).unwrap());
+            let res = next.run(req, ps);
+            Box::pin(async move {
+                let mut res = res.await?;
+                res.headers_mut()
+                    .append("x-trace", HeaderValue::from_static(name));
+                Ok(res)
+            })
+        }
+    }
+
+    fn echo_trace() -> Handler<()> {
+        Box::new(|req: Request<()>, _: Params| async move {
+            let trace = req.headers()["x-trace"].to_str().unwrap().to_string();
+            Ok::<_, Infallible>(Response::new(full(trace)))
+        })
+    }
+
+    async fn serve(router: &Router<Handler<()>>, path: &str) -> (Response<Body>, String) {
+        let req = Request::get(path).body(()).unwrap();
+        let (parts, body) = router.serve_http(req).await.unwrap().into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        let res = Response::from_parts(parts, empty());
+        (res, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn response_trace(res: &Response<Body>) -> Vec<&str> {
+        res.headers()
+            .get_all("x-trace")
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn middleware_order() {
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get("/", echo_trace());
+        {
+            let mut api = router.group("/api");
+            api.add_middleware(trace("api"));
+            let mut v1 = api.group("/v1");
+            v1.get("/users/:id", wrap(trace("route"), echo_trace()));
+            v1.add_middleware(trace("v1"));
+        }
+        router.add_middleware(trace("global1"));
+        router.add_middleware(trace("global2"));
+
+        let (res, body) = serve(&router, "/api/v1/users/42").await;
+        assert_eq!("global1 global2 api v1 route", body);
+        assert_eq!(
+            vec!["route", "v1", "api", "global2", "global1"],
+            response_trace(&res)
+        );
+
+        let (_, body) = serve(&router, "/").await;
+        assert_eq!("global1 global2", body);
+    }
+
+    #[tokio::test]
+    async fn middleware_fallbacks() {
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get("/users/", echo_trace());
+        router.add_middleware(trace("global"));
+        router.group("/api").add_middleware(trace("api"));
+
+        let (res, _) = serve(&router, "/api/nothing").await;
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert_eq!(vec!["api", "global"], response_trace(&res));
+
+        let (res, _) = serve(&router, "/users").await;
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, res.status());
+        assert_eq!(vec!["global"], response_trace(&res));
+
+        router.not_found = Some(echo_trace());
+        let (res, body) = serve(&router, "/api/nothing").await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("global api", body);
+    }
+
+    #[tokio::test]
+    async fn middleware_short_circuit() {
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get("/admin", echo_trace());
+        router.add_middleware(|req: Request<()>, ps: Params, next: Next<()>| -> BoxFut {
+            if req.headers().contains_key("x-trace") {
+                return next.run(req, ps);
+            }
+            let mut res = Response::new(empty());
+            *res.status_mut() = StatusCode::UNAUTHORIZED;
+            Box::pin(async { Ok(res) })
+        });
+
+        let (res, _) = serve(&router, "/admin").await;
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        let req = Request::get("/admin")
+            .header("x-trace", "me")
+            .body(())
+            .unwrap();
+        let res = router.serve_http(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+}
@@ -1,19 +1,21 @@
+use std::borrow::Cow;
+
 /// CleanPath is the URL version of path.Clean, it returns a canonical URL path
 /// for p, eliminating . and .. elements.
 ///
 /// The following rules are applied iteratively until no further processing can
 /// be done:
-///	1. Replace multiple slashes with a single slash.
-///	2. Eliminate each . path name element (the current directory).
-///	3. Eliminate each inner .. path name element (the parent directory)
-///	   along with the non-.. element that precedes it.
-///	4. Eliminate .. elements that begin a rooted path:
-///	   that is, replace "/.." by "/" at the beginning of a path.
+/// 1. Replace multiple slashes with a single slash.
+/// 2. Eliminate each . path name element (the current directory).
+/// 3. Eliminate each inner .. path name element (the parent directory)
+///    along with the non-.. element that precedes it.
+/// 4. Eliminate .. elements that begin a rooted path:
+///    that is, replace "/.." by "/" at the beginning of a path.
 ///
 /// If the result of this process is an empty string, "/" is returned
 pub fn clean_path(p: &str) -> String {
     // Turn empty string into "/"
-    if p == "" {
+    if p.is_empty() {
         return "/".to_string();
     }
 
@@ -21,10 +23,10 @@ pub fn clean_path(p: &str) -> String {
     let mut buf: Vec<u8> = Vec::new();
 
     // Invariants:
-	//      reading from path; r is index of next byte to process.
-	//      writing to buf; w is index of next byte to write.
+    //      reading from path; r is index of next byte to process.
+    //      writing to buf; w is index of next byte to write.
 
-	// path must start with '/'
+    // path must start with '/'
 
     let mut r = 1;
     let mut w = 1;
@@ -39,12 +41,12 @@ pub fn clean_path(p: &str) -> String {
     let p = p.as_bytes();
 
     // A bit more clunky without a 'lazybuf' like the path package, but the loop
-	// gets completely inlined (bufApp). So in contrast to the path package this
-	// loop has no expensive function calls (except 1x make)
+    // gets completely inlined (bufApp). So in contrast to the path package this
+    // loop has no expensive function calls (except 1x make)
 
     while r < n {
         match p[r] {
-            b'/' => r += 1,  // empty path element, trailing slash is added after the end
+            b'/' => r += 1, // empty path element, trailing slash is added after the end
             b'.' => {
                 if r + 1 == n {
                     trailing = true;
@@ -74,7 +76,7 @@ pub fn clean_path(p: &str) -> String {
             }
             _ => {
                 // real path element.
-			    // add slash if needed
+                // add slash if needed
                 if w > 1 {
                     buf_app(&mut buf, p, w, b'/');
                     w += 1;
@@ -114,6 +116,90 @@ fn buf_app(buf: &mut Vec<u8>, s: &[u8], w: usize, c: u8) {
     buf[w] = c;
 }
 
+/// EscapePath percent-encodes p so it can be used as a URL path.
+/// If keep_slash is false, '/' is escaped as well, so that p stays a single
+/// path segment.
+pub fn escape_path(p: &str, keep_slash: bool) -> String {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let mut buf = String::with_capacity(p.len());
+    for &c in p.as_bytes() {
+        match c {
+            b'/' if keep_slash => buf.push('/'),
+            // unreserved characters and sub-delims allowed in a path segment
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b'!'
+            | b'$'
+            | b'&'
+            | b'\''
+            | b'('
+            | b')'
+            | b'*'
+            | b'+'
+            | b','
+            | b';'
+            | b'='
+            | b':'
+            | b'@' => buf.push(c as char),
+            _ => {
+                buf.push('%');
+                buf.push(HEX[(c >> 4) as usize] as char);
+                buf.push(HEX[(c & 15) as usize] as char);
+            }
+        }
+    }
+    buf
+}
+
+/// UnescapePath decodes the percent-encoded bytes of p, e.g. "%2F" becomes
+/// "/". Malformed escapes are kept as they are. p is returned unchanged if it
+/// contains no escapes or does not decode to valid UTF-8.
+pub fn unescape_path(p: &str) -> Cow<'_, str> {
+    fn hex(c: u8) -> Option<u8> {
+        match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            b'A'..=b'F' => Some(c - b'A' + 10),
+            _ => None,
+        }
+    }
+
+    let s = p.as_bytes();
+    if !s.contains(&b'%') {
+        return Cow::Borrowed(p);
+    }
+
+    let mut buf = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        match (
+            s[i],
+            s.get(i + 1).cloned().and_then(hex),
+            s.get(i + 2).cloned().and_then(hex),
+        ) {
+            (b'%', Some(hi), Some(lo)) => {
+                buf.push(hi << 4 | lo);
+                i += 3;
+            }
+            (c, _, _) => {
+                buf.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    match String::from_utf8(buf) {
+        Ok(decoded) => Cow::Owned(decoded),
+        Err(_) => Cow::Borrowed(p),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -180,6 +266,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_escape_path() {
+        let tests = vec![
+            ("", false, ""),
+            ("abc", false, "abc"),
+            ("a b", false, "a%20b"),
+            ("a/b", false, "a%2Fb"),
+            ("a/b", true, "a/b"),
+            ("50%?#", false, "50%25%3F%23"),
+            ("Jürgen", false, "J%C3%BCrgen"),
+            ("user@host:8080", false, "user@host:8080"),
+        ];
+
+        for (p, keep_slash, result) in tests {
+            assert_eq!(result, escape_path(p, keep_slash));
+        }
+    }
+
+    #[test]
+    fn test_unescape_path() {
+        let tests = vec![
+            ("", ""),
+            ("abc", "abc"),
+            ("a%20b", "a b"),
+            ("a%2Fb", "a/b"),
+            ("a%2fb", "a/b"),
+            ("J%C3%BCrgen", "Jürgen"),
+            ("50%", "50%"),
+            ("50%2", "50%2"),
+            ("50%zz", "50%zz"),
+            ("%25%32%46", "%2F"),
+            // not valid UTF-8
+            ("%FF", "%FF"),
+        ];
+
+        for (p, result) in tests {
+            assert_eq!(result, unescape_path(p));
+            assert_eq!(p, unescape_path(&escape_path(p, false)));
+        }
+
+        assert!(match unescape_path("abc") {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        });
+    }
+
     // #[test]
     // fn test_path_clean_mallocs() {
 
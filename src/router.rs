@@ -1,52 +1,281 @@
-use futures::future;
-use hyper::rt::Future;
-use hyper::{Body, Method, Request, Response, StatusCode};
-use crate::path::clean_path;
+use crate::method::MethodMap;
+use crate::middleware::{Endpoint, Middleware, Next};
+use crate::path::{clean_path, escape_path, unescape_path};
+use crate::service::OriginalUri;
+use crate::tree::{compile_constraint, split_wildcard, wildcard_end, InsertError, Match, Node};
+use bytes::Bytes;
+use http::uri::{Authority, Scheme};
+use http::{header, Method, Request, Response, StatusCode};
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::Incoming;
+use smallvec::SmallVec;
+use std::any::Any;
+use std::borrow::Cow;
 use std::collections::BTreeMap;
-use std::ops::Index;
-use std::path::Path;
-use tokio_fs;
-use tokio_io;
-use crate::tree::Node;
 use std::error::Error as StdError;
+use std::fmt;
+use std::future::{self, Future};
+use std::mem;
+use std::ops::Index;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// BoxError is the error type of handlers and response bodies.
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Body is the response body returned by handlers. Any `http_body::Body`
+/// with `Bytes` chunks can be turned into one with `boxed`.
+pub type Body = UnsyncBoxBody<Bytes, BoxError>;
+
+/// BoxFut is the future returned by a handler.
+pub type BoxFut = Pin<Box<dyn Future<Output = Result<Response<Body>, BoxError>> + Send>>;
+
+/// Handle handles a request of body type `B`, hyper's `Incoming` by default.
+pub trait Handle<B = Incoming> {
+    fn handle(&self, req: Request<B>, ps: Params) -> BoxFut;
+}
+
+/// Any `async fn(Request<B>, Params) -> Result<Response<RB>, E>` (or closure
+/// returning a future) is a `Handle`, as long as `RB` is an `http_body::Body`
+/// and the errors can be boxed.
+impl<F, Fut, B, RB, E> Handle<B> for F
+where
+    F: Fn(Request<B>, Params) -> Fut,
+    Fut: Future<Output = Result<Response<RB>, E>> + Send + 'static,
+    RB: http_body::Body<Data = Bytes> + Send + 'static,
+    RB::Error: Into<BoxError>,
+    E: Into<BoxError>,
+{
+    fn handle(&self, req: Request<B>, ps: Params) -> BoxFut {
+        let fut = (*self)(req, ps);
+        Box::pin(async move {
+            match fut.await {
+                Ok(res) => Ok(res.map(boxed)),
+                Err(err) => Err(err.into()),
+            }
+        })
+    }
+}
 
-// TODO: think more about what a handler looks like
-// pub type Handle = fn(Request<Body>, Response<Body>, Option<Params>) -> BoxFut;
-// pub type ResponseFuture = Box<Future<Item=Response<Body>, Error=Error> + Send>;
-pub type BoxFut = Box<Future<Item = Response<Body>, Error = Box<dyn StdError + Sync + Send>> + Send>;
+/// Handler is the default handle type of a `Router`: a boxed `fn`, `async fn`
+/// or closure. Closures are able to capture outside parameters.
+pub type Handler<B = Incoming> = Box<dyn Handle<B> + Send + Sync>;
 
+/// Shared is a handler registered for several methods by `Router::route`,
+/// all of them calling the same `Arc`ed handler.
+struct Shared<B>(Arc<Handler<B>>);
 
-pub trait Handle {
-    fn handle(&self, req: Request<Body>, ps: Params) -> BoxFut;
+impl<B> Handle<B> for Shared<B> {
+    fn handle(&self, req: Request<B>, ps: Params) -> BoxFut {
+        self.0.handle(req, ps)
+    }
 }
 
-impl<F> Handle for F
+/// boxed turns any body with `Bytes` chunks into a `Body`.
+pub fn boxed<RB>(body: RB) -> Body
 where
-    F: Fn(Request<Body>, Params) -> BoxFut,
+    RB: http_body::Body<Data = Bytes> + Send + 'static,
+    RB::Error: Into<BoxError>,
 {
-    fn handle(&self, req: Request<Body>, ps: Params) -> BoxFut {
-        (*self)(req, ps)
+    body.map_err(Into::into).boxed_unsync()
+}
+
+/// full returns a `Body` holding all of `data`.
+pub fn full<D: Into<Bytes>>(data: D) -> Body {
+    boxed(Full::new(data.into()))
+}
+
+/// empty returns an empty `Body`.
+pub fn empty() -> Body {
+    boxed(Empty::new())
+}
+
+/// HandlerError is the error of a `ResponseFuture`, wrapping the error returned
+/// by a handler.
+///
+/// Unlike `BoxError` it is a concrete type, so a connection serving the router
+/// can be spawned onto a runtime: hyper requires the service error to convert
+/// into a `BoxError`, which rustc fails to prove for `BoxError` itself inside
+/// an `async` block.
+#[derive(Debug)]
+pub struct HandlerError(BoxError);
+
+impl HandlerError {
+    /// into_inner returns the error returned by the handler.
+    pub fn into_inner(self) -> BoxError {
+        self.0
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl StdError for HandlerError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&*self.0)
     }
 }
 
-// impl Handle for Handler {
-//     fn handle(&self, req: Request<Body>, ps: Option<Params>) -> BoxFut {
-//         (*self)(req, ps)
-//     }
-// }
+/// PanicHandler is called when a handler panics, with the method, URI,
+/// version and headers of the request and the payload of the panic.
+pub type PanicHandler = Arc<dyn Fn(Request<()>, Box<dyn Any + Send>) -> BoxFut + Send + Sync>;
+
+/// ResponseFuture is the future returned by `Router::serve_http`.
+///
+/// Panics while it is polled are caught and answered by the panic handler
+/// of the router, or with a 500 Internal Server Error.
+pub struct ResponseFuture {
+    inner: BoxFut,
+    // the request metadata and the handler to recover from a panic
+    recover: Option<(Request<()>, PanicHandler)>,
+}
 
-// pub type Handler = fn(Request<Body>, Option<Params>) -> BoxFut;
+impl Future for ResponseFuture {
+    type Output = Result<Response<Body>, HandlerError>;
 
-/// Handle is a function that can be registered to a route to handle HTTP
-/// requests. It has a third parameter for the values of
-/// wildcards (variables).
-pub type Handler = Box<Handle + Send + Sync>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            match panic::catch_unwind(AssertUnwindSafe(|| this.inner.as_mut().poll(cx))) {
+                Ok(poll) => return poll.map_err(HandlerError),
+                Err(payload) => this.inner = recover(this.recover.take(), payload),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResponseFuture").finish()
+    }
+}
+
+/// Returns the response to a panic. If the panic handler panics as well,
+/// it falls back to a 500 Internal Server Error.
+fn recover(recover: Option<(Request<()>, PanicHandler)>, payload: Box<dyn Any + Send>) -> BoxFut {
+    if let Some((req, panic_handler)) = recover {
+        if let Ok(fut) = panic::catch_unwind(AssertUnwindSafe(|| panic_handler(req, payload))) {
+            return fut;
+        }
+    }
+
+    let response = Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(full("INTERNAL_SERVER_ERROR"))
+        .unwrap();
+    ready(response)
+}
+
+/// ready wraps an already built response into a `BoxFut`.
+pub(crate) fn ready(response: Response<Body>) -> BoxFut {
+    Box::pin(future::ready(Ok(response)))
+}
+
+/// Returns the scheme and host the client used for the request, as reported
+/// by the X-Forwarded-Proto and X-Forwarded-Host headers of a proxy, the Host
+/// header or the request URI.
+fn origin<B>(req: &Request<B>) -> Option<(Scheme, Authority)> {
+    // proxies append their value to the list, the first one is the client's
+    let forwarded = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+    };
+
+    let host = forwarded("x-forwarded-host")
+        .or_else(|| {
+            req.headers()
+                .get(header::HOST)
+                .and_then(|value| value.to_str().ok())
+        })
+        .and_then(|host| host.parse::<Authority>().ok())
+        .or_else(|| req.uri().authority().cloned())?;
+    // the host must not carry credentials into the Location
+    if host.as_str().contains('@') {
+        return None;
+    }
+
+    let scheme = forwarded("x-forwarded-proto")
+        .and_then(|scheme| scheme.parse::<Scheme>().ok())
+        .filter(|scheme| *scheme == Scheme::HTTP || *scheme == Scheme::HTTPS)
+        .or_else(|| req.uri().scheme().cloned())
+        .unwrap_or(Scheme::HTTP);
+
+    Some((scheme, host))
+}
+
+/// Redirects is the redirect policy of a router, see `permanent_redirects`
+/// and `absolute_redirects`. Routers which do not use the default policy pass
+/// it to their handlers in the request extensions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Redirects {
+    pub permanent: bool,
+    pub absolute: bool,
+}
+
+impl Redirects {
+    /// Redirect builds the redirection of the request to the path, keeping
+    /// the query of the request.
+    pub(crate) fn redirect<B>(self, req: &Request<B>, path: &str) -> Response<Body> {
+        let code = if req.method() == Method::GET {
+            StatusCode::MOVED_PERMANENTLY
+        } else if self.permanent {
+            StatusCode::PERMANENT_REDIRECT
+        } else {
+            StatusCode::TEMPORARY_REDIRECT
+        };
+
+        let mut location = String::new();
+        if self.absolute {
+            if let Some((scheme, host)) = origin(req) {
+                location = format!("{}://{}", scheme, host);
+            }
+        }
+        location += path;
+        if let Some(query) = req.uri().query() {
+            location.push('?');
+            location += query;
+        }
+
+        Response::builder()
+            .header(header::LOCATION, location)
+            .status(code)
+            .body(empty())
+            .unwrap()
+    }
+}
+
+/// strip_body drops the body of the response to a HEAD request, setting the
+/// Content-Length to the exact size of the body if the handler did not.
+pub(crate) fn strip_body(response: BoxFut) -> BoxFut {
+    Box::pin(async move {
+        let (mut parts, body) = response.await?.into_parts();
+        if !parts.headers.contains_key(header::CONTENT_LENGTH) {
+            if let Some(len) = http_body::Body::size_hint(&body).exact() {
+                parts.headers.insert(header::CONTENT_LENGTH, len.into());
+            }
+        }
+        Ok(Response::from_parts(parts, empty()))
+    })
+}
 
 /// Param is a single URL parameter, consisting of a key and a value.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Param {
     pub key: String,
     pub value: String,
+    /// The value as it appeared in the request path, if it was
+    /// percent-decoded into `value`.
+    pub raw: Option<String>,
 }
 
 impl Param {
@@ -54,6 +283,24 @@ impl Param {
         Param {
             key: key.to_string(),
             value: value.to_string(),
+            raw: None,
+        }
+    }
+
+    /// Returns the value as it appeared in the request path.
+    pub fn raw_value(&self) -> &str {
+        self.raw.as_ref().unwrap_or(&self.value)
+    }
+
+    /// Percent-decodes the value, keeping the raw value. An encoded '/' is
+    /// decoded as well, it never separated path segments.
+    pub fn decode(&mut self) {
+        if self.raw.is_some() {
+            return;
+        }
+
+        if let Cow::Owned(value) = unescape_path(&self.value) {
+            self.raw = Some(mem::replace(&mut self.value, value));
         }
     }
 }
@@ -61,7 +308,7 @@ impl Param {
 /// Params is a Param-slice, as returned by the router.
 /// The slice is ordered, the first URL parameter is also the first slice value.
 /// It is therefore safe to read values by the index.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Params(pub Vec<Param>);
 
 impl Params {
@@ -86,6 +333,46 @@ impl Params {
     pub fn push(&mut self, p: Param) {
         self.0.push(p);
     }
+
+    /// Returns the raw value of the first Param which key matches the given
+    /// name, as it appeared in the request path.
+    pub fn raw_by_name(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|param| param.key == name)
+            .map(Param::raw_value)
+    }
+
+    /// Percent-decodes all values, see `Param::decode`.
+    pub fn decode(&mut self) {
+        for param in &mut self.0 {
+            param.decode();
+        }
+    }
+
+    /// Parses the value of the first Param which key matches the given name,
+    /// e.g. `params.get::<u64>("id")`.
+    pub fn get<T>(&self, name: &str) -> Result<T, ParamError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self.by_name(name).ok_or_else(|| ParamError::Missing {
+            name: name.to_string(),
+        })?;
+
+        value.parse().map_err(|err: T::Err| ParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+            reason: err.to_string(),
+        })
+    }
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params::new()
+    }
 }
 
 impl Index<usize> for Params {
@@ -96,12 +383,151 @@ impl Index<usize> for Params {
     }
 }
 
+impl<'k, 'v> From<ParamsRef<'k, 'v>> for Params {
+    fn from(params: ParamsRef<'k, 'v>) -> Params {
+        Params(
+            params
+                .iter()
+                .map(|(key, value)| Param::new(key, value))
+                .collect(),
+        )
+    }
+}
+
+/// ParamsRef is the borrowed counterpart of Params, as returned by
+/// `Node::get_value_ref`. The keys borrow from the tree and the values from
+/// the looked up path. A few params are stored inline without allocating.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ParamsRef<'k, 'v>(SmallVec<[(&'k str, &'v str); 4]>);
+
+impl<'k, 'v> ParamsRef<'k, 'v> {
+    /// Empty `ParamsRef`
+    pub fn new() -> ParamsRef<'k, 'v> {
+        ParamsRef(SmallVec::new())
+    }
+
+    /// ByName returns the value of the first param which key matches the given name.
+    pub fn by_name(&self, name: &str) -> Option<&'v str> {
+        self.iter()
+            .find(|&(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns an iterator over the (key, value) pairs in URL order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'k str, &'v str)> + '_ {
+        self.0.iter().cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.0.reserve(additional);
+    }
+
+    pub(crate) fn push(&mut self, key: &'k str, value: &'v str) {
+        self.0.push((key, value));
+    }
+
+    pub(crate) fn pop(&mut self) {
+        self.0.pop();
+    }
+}
+
+impl<'k, 'v> Index<usize> for ParamsRef<'k, 'v> {
+    type Output = str;
+
+    fn index(&self, i: usize) -> &Self::Output {
+        (self.0)[i].1
+    }
+}
+
+/// ParamError is returned if the params can not be extracted into the
+/// requested type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamError {
+    /// No param with the name was captured.
+    Missing { name: String },
+    /// The value of the param could not be parsed.
+    Invalid {
+        name: String,
+        value: String,
+        reason: String,
+    },
+    /// The params as a whole could not be deserialized, e.g. into a tuple of
+    /// the wrong length.
+    Custom { reason: String },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamError::Missing { name } => write!(f, "missing param '{}'", name),
+            ParamError::Invalid {
+                name,
+                value,
+                reason,
+            } => write!(
+                f,
+                "invalid value '{}' for param '{}': {}",
+                value, name, reason
+            ),
+            ParamError::Custom { reason } => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl StdError for ParamError {}
+
+/// UrlError is returned by `Router::url_for` if no URL can be built.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UrlError {
+    /// No route is registered with the name.
+    UnknownName { name: String },
+    /// The route has a parameter for which no value was given.
+    MissingParam { name: String, param: String },
+    /// A value was given for a parameter the route does not have.
+    ExtraParam { name: String, param: String },
+    /// The escaped value of a parameter does not match its constraint, so the
+    /// URL would not be routed to the named route.
+    InvalidParam {
+        name: String,
+        param: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UrlError::UnknownName { name } => write!(f, "no route named '{}'", name),
+            UrlError::MissingParam { name, param } => {
+                write!(f, "missing parameter '{}' for route '{}'", param, name)
+            }
+            UrlError::ExtraParam { name, param } => {
+                write!(f, "unknown parameter '{}' for route '{}'", param, name)
+            }
+            UrlError::InvalidParam { name, param, value } => write!(
+                f,
+                "value '{}' of parameter '{}' does not match its constraint in route '{}'",
+                value, param, name
+            ),
+        }
+    }
+}
+
+impl StdError for UrlError {}
+
 /// Router is container which can be used to dispatch requests to different
 /// handler functions via configurable routes
 // #[derive(Clone)]
-#[allow(dead_code)]
 pub struct Router<T> {
-    pub trees: BTreeMap<String, Node<T>>,
+    pub trees: MethodMap<Node<T>>,
 
     // Enables automatic redirection if the current route can't be matched but a
     // handler for the path with (without) the trailing slash exists.
@@ -121,6 +547,17 @@ pub struct Router<T> {
     // RedirectTrailingSlash is independent of this option.
     pub redirect_fixed_path: bool,
 
+    // If enabled, redirects of requests other than GET use the status code
+    // 308 (Permanent Redirect) instead of 307, which keeps the method and
+    // body like 307 but lets clients remember the redirection.
+    pub permanent_redirects: bool,
+
+    // If enabled, the Location of redirects is an absolute URL. The scheme
+    // and host are taken from the X-Forwarded-Proto and X-Forwarded-Host
+    // headers, falling back to the Host header and the request URI. The
+    // Location stays relative if no host is known.
+    pub absolute_redirects: bool,
+
     // If enabled, the router checks if another method is allowed for the
     // current route, if the current request can not be routed.
     // If this is the case, the request is answered with 'Method Not Allowed'
@@ -133,6 +570,19 @@ pub struct Router<T> {
     // Custom OPTIONS handlers take priority over automatic replies.
     pub handle_options: bool,
 
+    // If enabled, HEAD requests without a HEAD route are answered by the
+    // handler of the GET route for the path, even if a route for any method
+    // matches it, with the body stripped from the response. The
+    // Content-Length of the GET response is kept, and Allow lists HEAD
+    // wherever GET is allowed. Disabled by default.
+    pub handle_head: bool,
+
+    // If enabled, the values of params are percent-decoded before they are
+    // passed to the handler, e.g. /user/J%C3%BCrgen yields "Jürgen". Routes
+    // are still matched against the raw path, so an encoded '/' stays part
+    // of the param value. The raw values remain available via Param::raw.
+    pub decode_params: bool,
+
     // Configurable handler which is called when no matching route is
     // found.
     pub not_found: Option<T>,
@@ -147,8 +597,76 @@ pub struct Router<T> {
     // It should be used to generate a error page and return the http error code
     // 500 (Internal Server Error).
     // The handler can be used to keep your server from crashing because of
-    // unrecovered panics.
-    pub panic_handler: Option<T>,
+    // unrecovered panics. Without it, panics are answered with a plain 500.
+    pub panic_handler: Option<PanicHandler>,
+
+    // Fallback handlers registered for route groups. The innermost group
+    // matching the request path takes priority over the router-wide
+    // NotFound and MethodNotAllowed handlers.
+    pub(crate) scopes: Vec<Scope<T>>,
+
+    // Paths of the named routes, used to build URLs with url_for.
+    pub(crate) names: BTreeMap<String, String>,
+
+    // Middleware run in front of every handler, in the order it was added,
+    // see the middleware module.
+    pub(crate) middleware: Vec<Box<dyn Middleware<T>>>,
+
+    // Routes registered for any method with Router::any. The routes of the
+    // request method take precedence over them.
+    pub(crate) any: Node<T>,
+
+    // Routers and services mounted under a prefix, registered as catch-all
+    // routes "/prefix/*mount_path". They handle every method, but only paths
+    // not matched by the routes of this router.
+    pub(crate) mounts: Node<T>,
+}
+
+/// Scope holds the fallback handlers and the middleware of a route group.
+pub(crate) struct Scope<T> {
+    pub prefix: String,
+    pub not_found: Option<T>,
+    pub method_not_allowed: Option<T>,
+    pub middleware: Vec<Box<dyn Middleware<T>>>,
+}
+
+impl<T> Scope<T> {
+    /// Reports whether the path lies under the prefix of this scope.
+    /// Wildcards in the prefix match like they do in routes.
+    pub fn matches(&self, path: &str) -> bool {
+        let mut path_segments = path.split('/');
+
+        for segment in self.prefix.split('/') {
+            let path_segment = match path_segments.next() {
+                Some(path_segment) => path_segment,
+                None => return false,
+            };
+
+            match segment.find([':', '*']) {
+                Some(i) if segment.as_bytes()[i] == b'*' => {
+                    return path_segment.starts_with(&segment[..i]);
+                }
+                Some(i) => {
+                    if path_segment.len() <= i || !path_segment.starts_with(&segment[..i]) {
+                        return false;
+                    }
+                }
+                None => {
+                    if path_segment != segment {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+}
+
+impl<T> Default for Router<T> {
+    fn default() -> Self {
+        Router::new()
+    }
 }
 
 impl<T> Router<T> {
@@ -156,64 +674,85 @@ impl<T> Router<T> {
     /// Path auto-correction, including trailing slashes, is enabled by default.
     pub fn new() -> Router<T> {
         Router {
-            trees: BTreeMap::new(),
+            trees: MethodMap::new(),
             redirect_trailing_slash: true,
             redirect_fixed_path: true,
+            permanent_redirects: false,
+            absolute_redirects: false,
             handle_method_not_allowed: true,
             handle_options: true,
+            handle_head: false,
+            decode_params: false,
             not_found: None,
             method_not_allowed: None,
             panic_handler: None,
+            scopes: Vec::new(),
+            names: BTreeMap::new(),
+            middleware: Vec::new(),
+            any: Node::new(),
+            mounts: Node::new(),
         }
     }
 
-    /// get is a shortcut for router.handle("GET", path, handle)
+    /// get is a shortcut for router.handle(Method::GET, path, handle)
     pub fn get(&mut self, path: &str, handle: T) {
-        self.handle("GET", path, handle);
+        self.handle(Method::GET, path, handle);
     }
 
-    /// head is a shortcut for router.handle("HEAD", path, handle)
+    /// head is a shortcut for router.handle(Method::HEAD, path, handle)
     pub fn head(&mut self, path: &str, handle: T) {
-        self.handle("HEAD", path, handle);
+        self.handle(Method::HEAD, path, handle);
     }
 
-    /// options is a shortcut for router.handle("OPTIONS", path, handle)
+    /// options is a shortcut for router.handle(Method::OPTIONS, path, handle)
     pub fn options(&mut self, path: &str, handle: T) {
-        self.handle("OPTIONS", path, handle);
+        self.handle(Method::OPTIONS, path, handle);
     }
 
-    /// post is a shortcut for router.handle("POST", path, handle)
+    /// post is a shortcut for router.handle(Method::POST, path, handle)
     pub fn post(&mut self, path: &str, handle: T) {
-        self.handle("POST", path, handle);
+        self.handle(Method::POST, path, handle);
     }
 
-    /// put is a shortcut for router.handle("PUT", path, handle)
+    /// put is a shortcut for router.handle(Method::PUT, path, handle)
     pub fn put(&mut self, path: &str, handle: T) {
-        self.handle("PUT", path, handle);
+        self.handle(Method::PUT, path, handle);
     }
 
-    /// patch is a shortcut for router.handle("PATCH", path, handle)
+    /// patch is a shortcut for router.handle(Method::PATCH, path, handle)
     pub fn patch(&mut self, path: &str, handle: T) {
-        self.handle("PATCH", path, handle);
+        self.handle(Method::PATCH, path, handle);
     }
 
-    /// delete is a shortcut for router.handle("DELETE", path, handle)
+    /// delete is a shortcut for router.handle(Method::DELETE, path, handle)
     pub fn delete(&mut self, path: &str, handle: T) {
-        self.handle("DELETE", path, handle);
+        self.handle(Method::DELETE, path, handle);
+    }
+
+    /// AddMiddleware adds a middleware run in front of every handler and
+    /// in front of the responses of the router itself, like NotFound.
+    pub fn add_middleware<M: Middleware<T> + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Box::new(middleware));
     }
 
-    /// Unimplemented. Perhaps something like
+    /// Group returns a `Group` which registers routes under the given prefix.
     ///
     /// # Example
     ///
-    /// ```ignore
-    /// router.group(vec![middelware], |router| {
-    ///     router.get("/something", somewhere);
-    ///     router.post("/something", somewhere);
-    /// })
+    /// ```rust
+    /// extern crate radix_router;
+    /// use http::Method;
+    /// use radix_router::router::Router;
+    /// let mut router: Router<&str> = Router::new();
+    /// {
+    ///     let mut api = router.group("/api/v1");
+    ///     api.get("/users", "list users");
+    ///     api.post("/users", "create user");
+    /// }
+    /// assert_eq!(Some(&"list users"), router.lookup(&Method::GET, "/api/v1/users").0);
     /// ```
-    pub fn group() {
-        unimplemented!()
+    pub fn group(&mut self, prefix: &str) -> Group<'_, T> {
+        Group::new(self, prefix)
     }
 
     /// Handle registers a new request handle with the given path and method.
@@ -223,16 +762,249 @@ impl<T> Router<T> {
     ///
     /// This function is intended for bulk loading and to allow the usage of less
     /// frequently used, non-standardized or custom methods (e.g. for internal
-    /// communication with a proxy).
-    pub fn handle(&mut self, method: &str, path: &str, handle: T) {
-        if !path.starts_with("/") {
-            panic!("path must begin with '/' in path '{}'", path);
+    /// communication with a proxy), like `Method::from_bytes(b"PURGE")`.
+    ///
+    /// Panics if the path is invalid or conflicts with an existing route, see
+    /// `try_handle` for a non-panicking variant.
+    pub fn handle(&mut self, method: Method, path: &str, handle: T) {
+        if let Err(err) = self.try_handle(method, path, handle) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Like `handle`, but returns an `InsertError` instead of panicking, so
+    /// that routes loaded at runtime can be reported and skipped.
+    pub fn try_handle(&mut self, method: Method, path: &str, handle: T) -> Result<(), InsertError> {
+        if !path.starts_with('/') {
+            return Err(InsertError::MissingLeadingSlash {
+                path: path.to_string(),
+            });
         }
 
         self.trees
-            .entry(method.to_string())
-            .or_insert(Node::new())
-            .add_route(path, handle);
+            .get_or_insert_with(method, Node::new)
+            .try_add_route(path, handle)
+    }
+
+    /// Any registers a new request handle for every method. Routes registered
+    /// for the request method take precedence over it, e.g. a GET route for
+    /// the same path.
+    ///
+    /// Panics if the path is invalid or conflicts with an existing route, see
+    /// `try_any` for a non-panicking variant.
+    pub fn any(&mut self, path: &str, handle: T) {
+        if let Err(err) = self.try_any(path, handle) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Like `any`, but returns an `InsertError` instead of panicking.
+    pub fn try_any(&mut self, path: &str, handle: T) -> Result<(), InsertError> {
+        if !path.starts_with('/') {
+            return Err(InsertError::MissingLeadingSlash {
+                path: path.to_string(),
+            });
+        }
+
+        self.any.try_add_route(path, handle)
+    }
+
+    /// get_named is a shortcut for router.handle_named(Method::GET, name, path, handle)
+    pub fn get_named(&mut self, name: &str, path: &str, handle: T) {
+        self.handle_named(Method::GET, name, path, handle);
+    }
+
+    /// HandleNamed registers a new request handle like `handle` and gives the
+    /// route a name, which can be used to build URLs for it with `url_for`.
+    ///
+    /// The same name can be used for several methods of the same path.
+    pub fn handle_named(&mut self, method: Method, name: &str, path: &str, handle: T) {
+        if let Err(err) = self.try_handle_named(method, name, path, handle) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Like `handle_named`, but returns an `InsertError` instead of panicking.
+    pub fn try_handle_named(
+        &mut self,
+        method: Method,
+        name: &str,
+        path: &str,
+        handle: T,
+    ) -> Result<(), InsertError> {
+        if let Some(named) = self.names.get(name) {
+            if named != path {
+                return Err(InsertError::DuplicateName {
+                    name: name.to_string(),
+                    path: named.to_string(),
+                });
+            }
+        }
+
+        self.try_handle(method, path, handle)?;
+        self.names.insert(name.to_string(), path.to_string());
+        Ok(())
+    }
+
+    /// UrlFor builds the URL path of the route with the given name, filling
+    /// in its parameters with the percent-encoded values.
+    ///
+    /// The values of constrained parameters must match their constraint once
+    /// escaped, e.g. `1/2` does not match `:id<[0-9/]+>` as it is sent as
+    /// `1%2F2`.
+    ///
+    /// The value of a catch-all parameter may contain slashes, a leading slash
+    /// is optional.
+    ///
+    /// ```rust
+    /// extern crate radix_router;
+    /// use radix_router::router::Router;
+    /// let mut router: Router<()> = Router::new();
+    /// router.get_named("user_show", "/users/:id", ());
+    /// router.get_named("files", "/files/*filepath", ());
+    ///
+    /// assert_eq!(Ok("/users/42".to_string()), router.url_for("user_show", &[("id", "42")]));
+    /// assert_eq!(
+    ///     Ok("/files/a/b%20c.txt".to_string()),
+    ///     router.url_for("files", &[("filepath", "a/b c.txt")])
+    /// );
+    /// ```
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+        let path = self.names.get(name).ok_or_else(|| UrlError::UnknownName {
+            name: name.to_string(),
+        })?;
+
+        let mut url = String::with_capacity(path.len());
+        let mut keys = Vec::new();
+        let mut rest = path.as_str();
+
+        while let Some(i) = rest.find([':', '*']) {
+            url.push_str(&rest[..i]);
+            rest = &rest[i..];
+
+            // skips a constraint like :id<\d+>
+            let end = wildcard_end(rest.as_bytes(), 0);
+            let key = &rest[1..rest[..end].find('<').unwrap_or(end)];
+            let value = match params.iter().find(|param| param.0 == key) {
+                Some(param) => param.1,
+                None => {
+                    return Err(UrlError::MissingParam {
+                        name: name.to_string(),
+                        param: key.to_string(),
+                    })
+                }
+            };
+
+            let value = if rest.starts_with('*') {
+                // the '/' in front of the catch-all is already in the url
+                escape_path(value.trim_start_matches('/'), true)
+            } else {
+                escape_path(value, false)
+            };
+
+            // the escaped value is what the tree matches the constraint against
+            if let Some(constraint) = split_wildcard(&rest.as_bytes()[..end]).1 {
+                let constraint = compile_constraint(constraint, path)
+                    .expect("constraints of registered routes are valid");
+                if !constraint.is_match(value.as_bytes()) {
+                    return Err(UrlError::InvalidParam {
+                        name: name.to_string(),
+                        param: key.to_string(),
+                        value,
+                    });
+                }
+            }
+            url.push_str(&value);
+
+            keys.push(key);
+            rest = &rest[end..];
+        }
+        url.push_str(rest);
+
+        if let Some(param) = params.iter().find(|param| !keys.contains(&param.0)) {
+            return Err(UrlError::ExtraParam {
+                name: name.to_string(),
+                param: param.0.to_string(),
+            });
+        }
+
+        Ok(url)
+    }
+
+    /// Replace registers the handle like `handle`, but replaces and returns
+    /// the handle already registered for exactly this method and path instead
+    /// of panicking.
+    ///
+    /// Panics if the path is invalid or conflicts with another route, see
+    /// `try_replace` for a non-panicking variant.
+    pub fn replace(&mut self, method: Method, path: &str, handle: T) -> Option<T> {
+        match self.try_replace(method, path, handle) {
+            Ok(old) => old,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Like `replace`, but returns an `InsertError` instead of panicking.
+    pub fn try_replace(
+        &mut self,
+        method: Method,
+        path: &str,
+        handle: T,
+    ) -> Result<Option<T>, InsertError> {
+        if !path.starts_with('/') {
+            return Err(InsertError::MissingLeadingSlash {
+                path: path.to_string(),
+            });
+        }
+
+        self.trees
+            .get_or_insert_with(method, Node::new)
+            .try_replace_route(path, handle)
+    }
+
+    /// Remove unregisters the route with the given method and path (e.g.
+    /// "/user/:name") and returns its handle.
+    ///
+    /// Routes registered for any method are removed with `remove_any`, and
+    /// mounted routers and services with `unmount`.
+    pub fn remove(&mut self, method: &Method, path: &str) -> Option<T> {
+        let tree = self.trees.get_mut(method)?;
+        let handle = tree.remove_route(path);
+
+        if tree.is_empty() {
+            self.trees.remove(method);
+        }
+
+        // forget the name once no method is left for the route
+        if handle.is_some() && !self.trees.values().any(|tree| tree.has_route(path)) {
+            self.names.retain(|_, named| named != path);
+        }
+
+        handle
+    }
+
+    /// RemoveAny unregisters the route registered for any method with the
+    /// given path and returns its handle.
+    pub fn remove_any(&mut self, path: &str) -> Option<T> {
+        self.any.remove_route(path)
+    }
+
+    /// Returns an iterator over all registered routes as (method, path,
+    /// handle), e.g. to print a route table at startup.
+    ///
+    /// Routes for any method and mounted routers are listed once for every
+    /// method, a mount with the path `/prefix/*mount_path`.
+    pub fn routes(&self) -> impl Iterator<Item = (&Method, String, &T)> {
+        let routes = self.trees.iter().flat_map(|(method, tree)| {
+            tree.routes()
+                .map(move |(path, handle)| (method, path, handle))
+        });
+        let any = self.any.routes().chain(self.mounts.routes());
+        routes.chain(any.flat_map(move |(path, handle)| {
+            self.trees
+                .all_keys()
+                .map(move |method| (method, path.clone(), handle))
+        }))
     }
 
     /// Lookup allows the manual lookup of a method + path combo.
@@ -242,343 +1014,583 @@ impl<T> Router<T> {
     /// If the path was found, it returns the handle function and the path parameter
     /// values. Otherwise the third return value indicates whether a redirection to
     /// the same path with an extra / without the trailing slash should be performed.
-    pub fn lookup(&mut self, method: &str, path: &str) -> (Option<&T>, Params, bool) {
+    /// Param values are percent-decoded if decode_params is enabled.
+    pub fn lookup(&mut self, method: &Method, path: &str) -> (Option<&T>, Params, bool) {
+        let decode_params = self.decode_params;
         self.trees
             .get_mut(method)
-            .and_then(|n| Some(n.get_value(path)))
+            .map(|n| {
+                let (handle, mut ps, tsr) = n.get_value(path);
+                if decode_params {
+                    ps.decode();
+                }
+                (handle, ps, tsr)
+            })
             .unwrap_or((None, Params::new(), false))
     }
 
-    pub fn allowed(&self, path: &str, req_method: &str) -> String {
-        let mut allow = String::new();
-        if path == "*" {
-            for method in self.trees.keys() {
-                if method == "OPTIONS" {
-                    continue;
-                }
+    /// Like `lookup`, but the params borrow from the router and the path
+    /// instead of being copied. They are never percent-decoded.
+    pub fn lookup_ref<'t, 'p>(&'t self, method: &Method, path: &'p str) -> Match<'t, 'p, T> {
+        match self.trees.get(method) {
+            Some(root) => root.get_value_ref(path),
+            None => Match {
+                handle: None,
+                params: ParamsRef::new(),
+                tsr: false,
+            },
+        }
+    }
 
-                if allow.is_empty() {
-                    allow.push_str(method);
-                } else {
-                    allow.push_str(", ");
-                    allow.push_str(method);
-                }
+    /// Allowed returns the value of the Allow header for the path: the
+    /// methods other than the requested one with a route matching the path,
+    /// or all methods with a route for the path "*", followed by OPTIONS.
+    /// If a route for any method matches, all methods are allowed.
+    pub fn allowed(&self, path: &str, req_method: &Method) -> String {
+        let mut allow = String::new();
+        let mut push = |method: &Method| {
+            if method == req_method || method == Method::OPTIONS {
+                return;
+            }
+            if !allow.is_empty() {
+                allow.push_str(", ");
             }
+            allow.push_str(method.as_str());
+        };
+
+        let matches = |tree: &Node<T>| path == "*" || tree.has_value(path);
+        if !self.any.is_empty() && matches(&self.any) {
+            self.trees.all_keys().for_each(push);
         } else {
-            for method in self.trees.keys() {
-                if method == req_method || method == "OPTIONS" {
-                    continue;
+            // HEAD is answered by the GET route with handle_head
+            let get = self.handle_head && self.trees.get(&Method::GET).is_some_and(matches);
+            for method in self.trees.all_keys() {
+                if self.trees.get(method).is_some_and(matches) || (get && method == Method::HEAD) {
+                    push(method);
                 }
+            }
+        }
+
+        if !allow.is_empty() {
+            allow += ", OPTIONS";
+        }
 
-                self.trees.get(method).map(|tree| {
-                    let (handle, _, _) = tree.get_value(path);
+        allow
+    }
 
-                    if handle.is_some() {
-                        if allow.is_empty() {
-                            allow.push_str(method);
-                        } else {
-                            allow.push_str(", ");
-                            allow.push_str(method);
-                        }
-                    }
+    /// Returns the NotFound handler of the innermost group containing the
+    /// path, or the router-wide one.
+    pub fn not_found_for(&self, path: &str) -> Option<&T> {
+        self.scope_for(path, |scope| scope.not_found.as_ref())
+            .or(self.not_found.as_ref())
+    }
+
+    /// Returns the MethodNotAllowed handler of the innermost group containing
+    /// the path, or the router-wide one.
+    pub fn method_not_allowed_for(&self, path: &str) -> Option<&T> {
+        self.scope_for(path, |scope| scope.method_not_allowed.as_ref())
+            .or(self.method_not_allowed.as_ref())
+    }
+
+    fn scope_for<'a, F>(&'a self, path: &str, handle: F) -> Option<&'a T>
+    where
+        F: Fn(&'a Scope<T>) -> Option<&'a T>,
+    {
+        self.scopes
+            .iter()
+            .filter(|scope| scope.matches(path))
+            .filter_map(|scope| handle(scope).map(|h| (scope.prefix.len(), h)))
+            .max_by_key(|&(len, _)| len)
+            .map(|(_, h)| h)
+    }
+
+    fn scope_mut(&mut self, prefix: &str) -> &mut Scope<T> {
+        match self.scopes.iter().position(|scope| scope.prefix == prefix) {
+            Some(i) => &mut self.scopes[i],
+            None => {
+                self.scopes.push(Scope {
+                    prefix: prefix.to_string(),
+                    not_found: None,
+                    method_not_allowed: None,
+                    middleware: Vec::new(),
                 });
+                self.scopes.last_mut().unwrap()
             }
         }
+    }
+}
+
+/// Group registers routes on a `Router` under a common path prefix.
+/// Groups can be nested and carry their own NotFound and MethodNotAllowed
+/// handlers, which apply to every request below the prefix.
+pub struct Group<'a, T> {
+    router: &'a mut Router<T>,
+    prefix: String,
+}
+
+impl<'a, T> Group<'a, T> {
+    fn new(router: &'a mut Router<T>, prefix: &str) -> Group<'a, T> {
+        if !prefix.starts_with('/') {
+            panic!("prefix must begin with '/' in prefix '{}'", prefix);
+        }
 
-        if allow.len() > 0 {
-            allow += ", OPTIONS";
+        Group {
+            router,
+            prefix: prefix.trim_end_matches('/').to_string(),
         }
+    }
 
-        allow
+    /// Returns the prefix all routes of this group are registered under.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Group returns a nested group, its prefix is appended to the prefix of
+    /// this group.
+    pub fn group(&mut self, prefix: &str) -> Group<'_, T> {
+        let prefix = self.prefix.clone() + prefix;
+        Group::new(self.router, &prefix)
+    }
+
+    /// get is a shortcut for group.handle(Method::GET, path, handle)
+    pub fn get(&mut self, path: &str, handle: T) {
+        self.handle(Method::GET, path, handle);
+    }
+
+    /// head is a shortcut for group.handle(Method::HEAD, path, handle)
+    pub fn head(&mut self, path: &str, handle: T) {
+        self.handle(Method::HEAD, path, handle);
+    }
+
+    /// options is a shortcut for group.handle(Method::OPTIONS, path, handle)
+    pub fn options(&mut self, path: &str, handle: T) {
+        self.handle(Method::OPTIONS, path, handle);
+    }
+
+    /// post is a shortcut for group.handle(Method::POST, path, handle)
+    pub fn post(&mut self, path: &str, handle: T) {
+        self.handle(Method::POST, path, handle);
+    }
+
+    /// put is a shortcut for group.handle(Method::PUT, path, handle)
+    pub fn put(&mut self, path: &str, handle: T) {
+        self.handle(Method::PUT, path, handle);
+    }
+
+    /// patch is a shortcut for group.handle(Method::PATCH, path, handle)
+    pub fn patch(&mut self, path: &str, handle: T) {
+        self.handle(Method::PATCH, path, handle);
+    }
+
+    /// delete is a shortcut for group.handle(Method::DELETE, path, handle)
+    pub fn delete(&mut self, path: &str, handle: T) {
+        self.handle(Method::DELETE, path, handle);
+    }
+
+    /// Handle registers a new request handle with the given method and the
+    /// path appended to the prefix of the group.
+    pub fn handle(&mut self, method: Method, path: &str, handle: T) {
+        if let Err(err) = self.try_handle(method, path, handle) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Like `handle`, but returns an `InsertError` instead of panicking.
+    pub fn try_handle(&mut self, method: Method, path: &str, handle: T) -> Result<(), InsertError> {
+        if !path.starts_with('/') {
+            return Err(InsertError::MissingLeadingSlash {
+                path: path.to_string(),
+            });
+        }
+
+        self.router
+            .try_handle(method, &(self.prefix.clone() + path), handle)
+    }
+
+    /// Any registers a new request handle for every method with the path
+    /// appended to the prefix of the group, see `Router::any`.
+    pub fn any(&mut self, path: &str, handle: T) {
+        if let Err(err) = self.try_any(path, handle) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Like `any`, but returns an `InsertError` instead of panicking.
+    pub fn try_any(&mut self, path: &str, handle: T) -> Result<(), InsertError> {
+        if !path.starts_with('/') {
+            return Err(InsertError::MissingLeadingSlash {
+                path: path.to_string(),
+            });
+        }
+
+        self.router.try_any(&(self.prefix.clone() + path), handle)
+    }
+
+    /// get_named is a shortcut for group.handle_named(Method::GET, name, path, handle)
+    pub fn get_named(&mut self, name: &str, path: &str, handle: T) {
+        self.handle_named(Method::GET, name, path, handle);
+    }
+
+    /// HandleNamed registers a new named request handle with the given method
+    /// and the path appended to the prefix of the group.
+    pub fn handle_named(&mut self, method: Method, name: &str, path: &str, handle: T) {
+        if let Err(err) = self.try_handle_named(method, name, path, handle) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Like `handle_named`, but returns an `InsertError` instead of panicking.
+    pub fn try_handle_named(
+        &mut self,
+        method: Method,
+        name: &str,
+        path: &str,
+        handle: T,
+    ) -> Result<(), InsertError> {
+        if !path.starts_with('/') {
+            return Err(InsertError::MissingLeadingSlash {
+                path: path.to_string(),
+            });
+        }
+
+        self.router
+            .try_handle_named(method, name, &(self.prefix.clone() + path), handle)
+    }
+
+    /// Sets the handler which is called when no matching route is found for a
+    /// path below the prefix of this group.
+    pub fn not_found(&mut self, handle: T) {
+        self.router.scope_mut(&self.prefix).not_found = Some(handle);
+    }
+
+    /// Sets the handler which is called when a path below the prefix of this
+    /// group can not be routed and HandleMethodNotAllowed is true.
+    pub fn method_not_allowed(&mut self, handle: T) {
+        self.router.scope_mut(&self.prefix).method_not_allowed = Some(handle);
+    }
+
+    /// Adds a middleware which is run for every request below the prefix of
+    /// this group, after the middleware of the router and of outer groups.
+    pub fn add_middleware<M: Middleware<T> + 'static>(&mut self, middleware: M) {
+        self.router
+            .scope_mut(&self.prefix)
+            .middleware
+            .push(Box::new(middleware));
     }
 }
 
-/// Service makes the router capable for Hyper.
-// impl Service for Router<Handler>
-
-// {
-//     type ReqBody = Body;
-//     type ResBody = Body;
-//     type Error = Error;
-//     type Future = BoxFut;
-
-//     /// call makes the router implement the `Service` trait.
-//     fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
-//         // let (handle, p, _) = self.lookup(req.method().as_str(), req.uri().path());
-//         // match handle {
-//         //     Some(h) => future::ok(h(req, p)),
-//         //     // Handle 404
-//         //     _ => future::ok(Response::new(Body::from("not found"))),
-//         // }
-//         // self.serve_http(req)
-//         // let method = req.method().as_str();
-//         // let path = req.uri().path();
-//         // let mut response = Response::new(Body::empty());
-
-//         let root = self.trees.get(req.method().as_str());
-//         if let Some(root) = root {
-//             let (handle, ps, tsr) = root.get_value(req.uri().path());
-
-//             if let Some(handle) = handle {
-//                 // return handle(req, response, ps);
-//                 return handle.handle(req, ps);
-//             } else if req.method() != &Method::CONNECT && req.uri().path() != "/" {
-//                 let code = if req.method() != &Method::GET {
-//                     // StatusCode::from_u16(307).unwrap()
-//                     307
-//                 } else {
-//                     // StatusCode::from_u16(301).unwrap()
-//                     301
-//                 };
-
-//                 if tsr && self.redirect_trailing_slash {
-//                     let path = if req.uri().path().len() > 1 && req.uri().path().ends_with("/") {
-//                         req.uri().path()[..req.uri().path().len() - 1].to_string()
-//                     } else {
-//                         req.uri().path().to_string() + "/"
-//                     };
-
-//                     // response.headers_mut().insert(header::LOCATION, header::HeaderValue::from_str(&path).unwrap());
-//                     // *response.status_mut() = code;
-//                     let response = Response::builder().header("Location", path.as_str()).status(code).body(Body::empty()).unwrap();
-//                     return Box::new(future::ok(response));
-//                 }
-
-//                 if self.redirect_fixed_path {
-//                     let (fixed_path, found) = root.find_case_insensitive_path(&clean_path(req.uri().path()), self.redirect_trailing_slash);
-
-//                     if found {
-//                         //  response.headers_mut().insert(header::LOCATION, header::HeaderValue::from_str(&fixed_path).unwrap());
-//                         // *response.status_mut() = code;
-//                         let response = Response::builder().header("Location", fixed_path.as_str()).status(code).body(Body::empty()).unwrap();
-//                         return Box::new(future::ok(response));
-//                     }
-//                 }
-//             }
-//         }
-
-//         if req.method() == &Method::OPTIONS && self.handle_options {
-//             let allow = self.allowed(req.uri().path(), req.method().as_str());
-//             if allow.len() > 0 {
-//                 // *response.headers_mut().get_mut("allow").unwrap() = header::HeaderValue::from_str(&allow).unwrap();
-//                 let response = Response::builder().header("Allow", allow.as_str()).body(Body::empty()).unwrap();
-//                 return Box::new(future::ok(response));
-//             }
-
-//         } else {
-//             if self.handle_method_not_allowed {
-//                 let allow = self.allowed(req.uri().path(), req.method().as_str());
-
-//                 if allow.len() > 0 {
-//                     let mut response = Response::builder().header("Allow", allow.as_str()).body(Body::empty()).unwrap();
-
-//                     if let Some(ref method_not_allowed) = self.method_not_allowed {
-//                         return method_not_allowed.handle(req, Params::new());
-//                     } else {
-//                         *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
-//                         *response.body_mut() = Body::from("METHOD_NOT_ALLOWED");
-//                     }
-
-//                     return Box::new(future::ok(response));
-//                 }
-//             }
-
-//         }
-
-//         // Handle 404
-//         if let Some(ref not_found) = self.not_found {
-//             return not_found.handle(req, Params::new());
-//         } else {
-//             // *response.status_mut() = StatusCode::NOT_FOUND;
-//             let response = Response::builder().status(404).body("NOT_FOUND".into()).unwrap();
-//             return Box::new(future::ok(response));
-//         }
-//     }
-// }
-
-// impl IntoFuture for Router<Handler>{
-//     type Future = future::FutureResult<Self::Item, Self::Error>;
-//     type Item = Self;
-//     type Error = Error;
-
-//     fn into_future(self) -> Self::Future {
-//         future::ok(self)
-//     }
-// }
-
-impl Router<Handler> {
-    /// ServeFiles serves files from the given file system root.
-    ///
-    /// The path must end with "/*filepath", files are then served from the local
-    /// path /defined/root/dir/*filepath.
-    ///
-    /// For example if root is "/etc" and *filepath is "passwd", the local file
-    /// "/etc/passwd" would be served.
+impl<'a, B: 'static> Group<'a, Handler<B>> {
+    /// Route registers the handle for each of the methods with the path
+    /// appended to the prefix of the group, see `Router::route`.
+    pub fn route(&mut self, methods: &[Method], path: &str, handle: Handler<B>) {
+        if let Err(err) = self.try_route(methods, path, handle) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Like `route`, but returns an `InsertError` instead of panicking.
+    pub fn try_route(
+        &mut self,
+        methods: &[Method],
+        path: &str,
+        handle: Handler<B>,
+    ) -> Result<(), InsertError> {
+        if !path.starts_with('/') {
+            return Err(InsertError::MissingLeadingSlash {
+                path: path.to_string(),
+            });
+        }
+
+        self.router
+            .try_route(methods, &(self.prefix.clone() + path), handle)
+    }
+}
+
+impl<B> Router<Handler<B>> {
+    /// Route registers the handle for each of the methods, sharing it through
+    /// an `Arc` instead of requiring a handle per method.
     ///
     /// ```rust
-    /// extern crate radix_router;
-    /// use radix_router::router::{Router, Handler};
+    /// use http::{Method, Request, Response};
+    /// use hyper::body::Incoming;
+    /// use radix_router::router::{full, Handler, Params, Router};
+    /// use std::convert::Infallible;
+    ///
     /// let mut router: Router<Handler> = Router::new();
-    /// router.serve_files("/examples/*filepath", "examples");
+    /// router.route(
+    ///     &[Method::GET, Method::HEAD],
+    ///     "/health",
+    ///     Box::new(|_: Request<Incoming>, _: Params| async {
+    ///         Ok::<_, Infallible>(Response::new(full("ok")))
+    ///     }),
+    /// );
+    /// assert_eq!("HEAD, OPTIONS", router.allowed("/health", &Method::GET));
     /// ```
-    pub fn serve_files(&mut self, path: &str, root: &'static str) {
-        if path.as_bytes().len() < 10 || &path[path.len() - 10..] != "/*filepath" {
-            panic!("path must end with /*filepath in path '{}'", path);
-        }
-        let root_path = Path::new(root);
-        let get_files = move |_, ps: Params| -> BoxFut {
-            let filepath = ps.by_name("filepath").unwrap();
-            simple_file_send(root_path.join(&filepath[1..]).to_str().unwrap())
+    ///
+    /// Panics if the path is invalid or conflicts with an existing route, see
+    /// `try_route` for a non-panicking variant.
+    pub fn route(&mut self, methods: &[Method], path: &str, handle: Handler<B>)
+    where
+        B: 'static,
+    {
+        if let Err(err) = self.try_route(methods, path, handle) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Like `route`, but returns an `InsertError` instead of panicking. The
+    /// handle stays registered for the methods before the failing one.
+    pub fn try_route(
+        &mut self,
+        methods: &[Method],
+        path: &str,
+        handle: Handler<B>,
+    ) -> Result<(), InsertError>
+    where
+        B: 'static,
+    {
+        let handle = Arc::new(handle);
+        for method in methods {
+            self.try_handle(method.clone(), path, Box::new(Shared(handle.clone())))?;
+        }
+        Ok(())
+    }
+
+    /// ServeHttp routes the request to its handler, or answers it with a
+    /// redirect, the allowed methods or an error response.
+    ///
+    /// Panics of the handlers and middleware, both when they are called and
+    /// while their futures are polled, are passed to the panic handler.
+    pub fn serve_http(&self, req: Request<B>) -> ResponseFuture {
+        let mut recover = self.panic_handler.as_ref().map(|panic_handler| {
+            let mut metadata = Request::new(());
+            *metadata.method_mut() = req.method().clone();
+            *metadata.uri_mut() = req.uri().clone();
+            *metadata.version_mut() = req.version();
+            *metadata.headers_mut() = req.headers().clone();
+            (metadata, panic_handler.clone())
+        });
+
+        let inner = match panic::catch_unwind(AssertUnwindSafe(|| self.dispatch(req))) {
+            Ok(inner) => inner,
+            Err(payload) => self::recover(recover.take(), payload),
         };
+        ResponseFuture { inner, recover }
+    }
+
+    fn dispatch(&self, mut req: Request<B>) -> BoxFut {
+        // handlers redirecting on their own, like the ones serving files,
+        // follow the policy of the innermost router
+        let redirects = self.redirects();
+        if redirects != Redirects::default() || req.extensions().get::<Redirects>().is_some() {
+            req.extensions_mut().insert(redirects);
+        }
+
+        let middleware = self.middleware_for(req.uri().path());
+        let (endpoint, mut ps) = self.find_endpoint(&req);
+
+        // a router mounted on another one, or called as a service by one,
+        // gets the params matched so far in the request extensions
+        if let Some(parent) = req.extensions().get::<Params>() {
+            ps.0.splice(0..0, parent.0.iter().cloned());
+        }
+        Next::new(&middleware, endpoint).run(req, ps)
+    }
 
-        self.get(path, Box::new(get_files));
+    /// Returns the router-wide middleware followed by the middleware of the
+    /// groups containing the path, from the outermost to the innermost.
+    fn middleware_for(&self, path: &str) -> SmallVec<[&dyn Middleware<Handler<B>>; 8]> {
+        let mut scopes: SmallVec<[&Scope<Handler<B>>; 4]> = self
+            .scopes
+            .iter()
+            .filter(|scope| !scope.middleware.is_empty() && scope.matches(path))
+            .collect();
+        scopes.sort_by_key(|scope| scope.prefix.len());
+
+        self.middleware
+            .iter()
+            .chain(scopes.iter().flat_map(|scope| scope.middleware.iter()))
+            .map(|middleware| -> &dyn Middleware<Handler<B>> { &**middleware })
+            .collect()
     }
 
-    pub fn serve_http(&self, req: Request<Body>) -> BoxFut {
-        let root = self.trees.get(req.method().as_str());
-        if let Some(root) = root {
-            let (handle, ps, tsr) = root.get_value(req.uri().path());
+    /// Returns the mounted router or service whose prefix the path lies
+    /// under, with the params of the prefix and the rest of the path as the
+    /// last param.
+    fn mount_for(&self, path: &str) -> Option<(Endpoint<'_, B>, Params)> {
+        let (handle, ps, tsr) = self.mounts.get_value(path);
+        let (handle, mut ps) = match handle {
+            Some(handle) => (handle, ps),
+            // the prefix itself, which the catch-all after it does not match
+            None if tsr && !path.ends_with('/') => {
+                let (handle, ps, _) = self.mounts.get_value(&(path.to_string() + "/"));
+                (handle?, ps)
+            }
+            None => return None,
+        };
+
+        if self.decode_params {
+            ps.decode();
+        }
+        Some((Endpoint::Handle(&**handle), ps))
+    }
 
-            if let Some(handle) = handle {
-                // return handle(req, response, ps);
-                return handle.handle(req, ps);
-            } else if req.method() != &Method::CONNECT && req.uri().path() != "/" {
-                let code = if req.method() != &Method::GET {
-                    // StatusCode::from_u16(307).unwrap()
-                    307
+    /// Returns the redirect policy of the router.
+    fn redirects(&self) -> Redirects {
+        Redirects {
+            permanent: self.permanent_redirects,
+            absolute: self.absolute_redirects,
+        }
+    }
+
+    /// Redirect builds the redirection of the request to the path, keeping
+    /// the query of the request. The path of a mounted router is redirected
+    /// below the prefix it is mounted at.
+    fn redirect(&self, req: &Request<B>, path: &str) -> Response<Body> {
+        let prefix = req
+            .extensions()
+            .get::<OriginalUri>()
+            .and_then(|original| original.0.path().strip_suffix(req.uri().path()))
+            .filter(|prefix| !prefix.is_empty());
+
+        match prefix {
+            Some(prefix) => self.redirects().redirect(req, &(prefix.to_string() + path)),
+            None => self.redirects().redirect(req, path),
+        }
+    }
+
+    /// FindEndpoint finds the handler for the request, or builds the response
+    /// of the router if there is none.
+    fn find_endpoint(&self, req: &Request<B>) -> (Endpoint<'_, B>, Params) {
+        let root = self.trees.get(req.method());
+        let (mut handle, mut ps, mut tsr) = match root {
+            Some(root) => root.get_value(req.uri().path()),
+            None => (None, Params::new(), false),
+        };
+
+        // HEAD falls back to the GET route, so that it mirrors GET even if a
+        // route for any method matches the path too
+        let get = Some(Method::GET)
+            .filter(|_| self.handle_head && req.method() == Method::HEAD)
+            .and_then(|get| self.trees.get(&get));
+        let mut head = false;
+        if let (None, Some(get)) = (&handle, get) {
+            let (get_handle, get_ps, get_tsr) = get.get_value(req.uri().path());
+            if get_handle.is_some() {
+                handle = get_handle;
+                ps = get_ps;
+                head = true;
+            }
+            tsr = tsr || get_tsr;
+        }
+
+        // routes for any method come after the routes of the method
+        if handle.is_none() && !self.any.is_empty() {
+            let (any_handle, any_ps, any_tsr) = self.any.get_value(req.uri().path());
+            if any_handle.is_some() {
+                handle = any_handle;
+                ps = any_ps;
+            }
+            tsr = tsr || any_tsr;
+        }
+
+        if let Some(handle) = handle {
+            if self.decode_params {
+                ps.decode();
+            }
+            if head {
+                return (Endpoint::Head(&**handle), ps);
+            }
+            return (Endpoint::Handle(&**handle), ps);
+        }
+
+        // routes of the router take precedence over mounted routers
+        if let Some(mounted) = self.mount_for(req.uri().path()) {
+            return mounted;
+        }
+
+        let any = Some(&self.any).filter(|any| !any.is_empty());
+        if (root.is_some() || any.is_some() || get.is_some())
+            && req.method() != Method::CONNECT
+            && req.uri().path() != "/"
+        {
+            if tsr && self.redirect_trailing_slash {
+                let path = if req.uri().path().len() > 1 && req.uri().path().ends_with("/") {
+                    req.uri().path()[..req.uri().path().len() - 1].to_string()
                 } else {
-                    // StatusCode::from_u16(301).unwrap()
-                    301
+                    req.uri().path().to_string() + "/"
                 };
 
-                if tsr && self.redirect_trailing_slash {
-                    let path = if req.uri().path().len() > 1 && req.uri().path().ends_with("/") {
-                        req.uri().path()[..req.uri().path().len() - 1].to_string()
-                    } else {
-                        req.uri().path().to_string() + "/"
-                    };
-
-                    // response.headers_mut().insert(header::LOCATION, header::HeaderValue::from_str(&path).unwrap());
-                    // *response.status_mut() = code;
-                    let response = Response::builder()
-                        .header("Location", path.as_str())
-                        .status(code)
-                        .body(Body::empty())
-                        .unwrap();
-                    return Box::new(future::ok(response));
-                }
+                return (Endpoint::Response(self.redirect(req, &path)), Params::new());
+            }
 
-                if self.redirect_fixed_path {
-                    let (fixed_path, found) = root.find_case_insensitive_path(
-                        &clean_path(req.uri().path()),
-                        self.redirect_trailing_slash,
-                    );
+            if self.redirect_fixed_path {
+                let clean = clean_path(req.uri().path());
+                for root in root.into_iter().chain(get).chain(any) {
+                    let (fixed_path, found) =
+                        root.find_case_insensitive_path(&clean, self.redirect_trailing_slash);
 
                     if found {
-                        //  response.headers_mut().insert(header::LOCATION, header::HeaderValue::from_str(&fixed_path).unwrap());
-                        // *response.status_mut() = code;
-                        let response = Response::builder()
-                            .header("Location", fixed_path.as_str())
-                            .status(code)
-                            .body(Body::empty())
-                            .unwrap();
-                        return Box::new(future::ok(response));
+                        return (
+                            Endpoint::Response(self.redirect(req, &fixed_path)),
+                            Params::new(),
+                        );
                     }
                 }
             }
         }
 
-        if req.method() == &Method::OPTIONS && self.handle_options {
-            let allow = self.allowed(req.uri().path(), req.method().as_str());
-            if allow.len() > 0 {
+        if req.method() == Method::OPTIONS && self.handle_options {
+            let allow = self.allowed(req.uri().path(), req.method());
+            if !allow.is_empty() {
                 // *response.headers_mut().get_mut("allow").unwrap() = header::HeaderValue::from_str(&allow).unwrap();
                 let response = Response::builder()
                     .header("Allow", allow.as_str())
-                    .body(Body::empty())
+                    .body(empty())
                     .unwrap();
-                return Box::new(future::ok(response));
+                return (Endpoint::Response(response), Params::new());
             }
         } else {
             if self.handle_method_not_allowed {
-                let allow = self.allowed(req.uri().path(), req.method().as_str());
+                let allow = self.allowed(req.uri().path(), req.method());
 
-                if allow.len() > 0 {
+                if !allow.is_empty() {
                     let mut response = Response::builder()
                         .header("Allow", allow.as_str())
-                        .body(Body::empty())
+                        .body(empty())
                         .unwrap();
 
-                    if let Some(ref method_not_allowed) = self.method_not_allowed {
-                        return method_not_allowed.handle(req, Params::new());
+                    if let Some(method_not_allowed) = self.method_not_allowed_for(req.uri().path())
+                    {
+                        return (Endpoint::Handle(&**method_not_allowed), Params::new());
                     } else {
                         *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
-                        *response.body_mut() = Body::from("METHOD_NOT_ALLOWED");
+                        *response.body_mut() = full("METHOD_NOT_ALLOWED");
                     }
 
-                    return Box::new(future::ok(response));
+                    return (Endpoint::Response(response), Params::new());
                 }
             }
         }
 
         // Handle 404
-        if let Some(ref not_found) = self.not_found {
-            return not_found.handle(req, Params::new());
+        if let Some(not_found) = self.not_found_for(req.uri().path()) {
+            (Endpoint::Handle(&**not_found), Params::new())
         } else {
             // *response.status_mut() = StatusCode::NOT_FOUND;
             let response = Response::builder()
                 .status(404)
-                .body("NOT_FOUND".into())
+                .body(full("NOT_FOUND"))
                 .unwrap();
-            return Box::new(future::ok(response));
-        }
-    }
-}
-
-fn simple_file_send(f: &str) -> BoxFut {
-    // Serve a file by asynchronously reading it entirely into memory.
-    // Uses tokio_fs to open file asynchronously, then tokio_io to read into
-    // memory asynchronously.
-    let filename = f.to_string(); // we need to copy for lifetime issues
-    Box::new(
-        tokio_fs::file::File::open(filename)
-            .and_then(|file| {
-                let buf: Vec<u8> = Vec::new();
-                tokio_io::io::read_to_end(file, buf)
-                    .and_then(|item| Ok(Response::new(item.1.into())))
-                    .or_else(|_| {
-                        Ok(Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .body(Body::empty())
-                            .unwrap())
-                    })
-            })
-            .or_else(|_| {
-                Ok(Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(Body::from("NOT_FOUND"))
-                    .unwrap())
-            }),
-    )
+            (Endpoint::Response(response), Params::new())
+        }
+    }
 }
 
-// impl<T> NewService for Router<T>
-// where
-//     T: Fn(Request<Body>, Response<Body>, Option<Params>) -> BoxFut,
-// {
-//     type ReqBody = Body;
-//     type ResBody = Body;
-//     type Error = Error;
-//     type Service = Self;
-//     type Future = future::FutureResult<Self::Service, Self::InitError>;
-//     type InitError = Error;
-
-//     fn new_service(&self) -> Self::Future {
-//         future::ok(*self.clone())
-//     }
-// }
-
 #[cfg(test)]
 mod tests {
+    use http::Method;
+
     #[test]
     fn params() {
         use crate::router::{Param, Params};
@@ -587,10 +1599,12 @@ mod tests {
             Param {
                 key: "hello".to_owned(),
                 value: "world".to_owned(),
+                raw: None,
             },
             Param {
                 key: "lalala".to_string(),
                 value: "papapa".to_string(),
+                raw: None,
             },
         ]);
 
@@ -598,19 +1612,794 @@ mod tests {
         assert_eq!(Some("papapa"), params.by_name("lalala"));
     }
 
+    #[test]
+    fn params_get() {
+        use crate::router::{Param, ParamError, Params};
+
+        let params = Params(vec![Param::new("id", "42"), Param::new("name", "gordon")]);
+
+        assert_eq!(Ok(42), params.get::<u64>("id"));
+        assert_eq!(Ok("gordon".to_string()), params.get::<String>("name"));
+        assert_eq!(
+            Err(ParamError::Missing {
+                name: "page".to_string()
+            }),
+            params.get::<u32>("page")
+        );
+
+        match params.get::<u64>("name") {
+            Err(ParamError::Invalid { name, value, .. }) => {
+                assert_eq!("name", name);
+                assert_eq!("gordon", value);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
     #[test]
     #[should_panic(expected = "path must begin with '/' in path 'something'")]
     fn handle_ivalid_path() {
-        // use http::Response;
-        use futures::future;
-        use hyper::{Body, Request, Response};
-        use crate::router::{BoxFut, Params, Router};
+        use crate::router::{full, Handler, Params, Router};
+        use http::{Request, Response};
+        use std::convert::Infallible;
 
         let path = "something";
+        let mut router: Router<Handler<()>> = Router::new();
+
+        router.handle(
+            Method::GET,
+            path,
+            Box::new(|_req: Request<()>, _: Params| async {
+                Ok::<_, Infallible>(Response::new(full("test")))
+            }),
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_http() {
+        use crate::router::{full, BoxError, Handler, Params, Router};
+        use http::{Request, Response, StatusCode};
+        use http_body_util::BodyExt;
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get(
+            "/users/:name",
+            Box::new(|_req: Request<()>, ps: Params| async move {
+                Ok::<_, BoxError>(Response::new(full(format!("hello, {}", &ps[0]))))
+            }),
+        );
+        router.get(
+            "/fail",
+            Box::new(|_req: Request<()>, _: Params| async { Err::<Response<String>, _>("failed") }),
+        );
+
+        let get = |method: &str, path: &str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(())
+                .unwrap();
+            router.serve_http(req)
+        };
+
+        let res = get("GET", "/users/gordon").await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!("hello, gordon", body);
+
+        let res = get("GET", "/users/gordon/").await.unwrap();
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, res.status());
+        assert_eq!("/users/gordon", res.headers()["Location"]);
+
+        let res = get("POST", "/users/gordon").await.unwrap();
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, res.status());
+        assert_eq!("GET, OPTIONS", res.headers()["Allow"]);
+
+        let res = get("GET", "/nothing").await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let err = get("GET", "/fail").await.unwrap_err();
+        assert_eq!("failed", err.to_string());
+    }
+
+    #[tokio::test]
+    async fn panic_handler() {
+        use crate::router::{full, BoxFut, Handler, Params, Router};
+        use http::{Request, Response, StatusCode};
+        use http_body_util::BodyExt;
+        use std::convert::Infallible;
+        use std::sync::Arc;
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get(
+            "/call",
+            Box::new(|_req: Request<()>, _: Params| -> BoxFut { panic!("in call") }),
+        );
+        router.get(
+            "/poll",
+            Box::new(|_req: Request<()>, _: Params| async {
+                if true {
+                    panic!("in poll");
+                }
+                Ok::<_, Infallible>(Response::new(full("unreachable")))
+            }),
+        );
+
+        let get = |router: &Router<Handler<()>>, path: &str| {
+            let req = Request::get(path).header("x-id", "7").body(()).unwrap();
+            router.serve_http(req)
+        };
+
+        for path in &["/call", "/poll"] {
+            let res = get(&router, path).await.unwrap();
+            assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+        }
+
+        router.panic_handler = Some(Arc::new(|req, payload| {
+            let msg = payload.downcast_ref::<&str>().unwrap();
+            let id = req.headers()["x-id"].to_str().unwrap();
+            let body = format!("{} {} {}: {}", req.method(), req.uri(), id, msg);
+            Box::pin(async { Ok(Response::builder().status(503).body(full(body)).unwrap()) })
+        }));
+
+        for (path, msg) in &[("/call", "in call"), ("/poll", "in poll")] {
+            let res = get(&router, path).await.unwrap();
+            assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(format!("GET {} 7: {}", path, msg), body);
+        }
+
+        router.panic_handler = Some(Arc::new(|_, _| panic!("in panic handler")));
+        let res = get(&router, "/poll").await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+    }
+
+    #[tokio::test]
+    async fn any_and_route() {
+        use crate::router::{full, Handler, Params, Router};
+        use http::{Request, Response, StatusCode};
+        use http_body_util::BodyExt;
+        use std::convert::Infallible;
+
+        fn reply(body: &'static str) -> Handler<()> {
+            Box::new(move |_: Request<()>, _: Params| async move {
+                Ok::<_, Infallible>(Response::new(full(body)))
+            })
+        }
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.any("/echo/:name", reply("any"));
+        router.get("/echo/:name", reply("get"));
+        router.route(&[Method::GET, Method::HEAD], "/health", reply("health"));
+        router
+            .group("/api")
+            .route(&[Method::PUT, Method::PATCH], "/users/:id", reply("update"));
+
+        let serve = |method: Method, path: &str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(())
+                .unwrap();
+            router.serve_http(req)
+        };
+        let body = |res: Response<_>| async move {
+            let body: http_body_util::combinators::UnsyncBoxBody<_, _> = res.into_body();
+            body.collect().await.unwrap().to_bytes()
+        };
+
+        assert_eq!(
+            "get",
+            body(serve(Method::GET, "/echo/x").await.unwrap()).await
+        );
+        assert_eq!(
+            "any",
+            body(serve(Method::POST, "/echo/x").await.unwrap()).await
+        );
+        let purge = Method::from_bytes(b"PURGE").unwrap();
+        assert_eq!("any", body(serve(purge, "/echo/x").await.unwrap()).await);
+        let res = serve(Method::GET, "/echo/x/").await.unwrap();
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, res.status());
+
+        assert_eq!(
+            "health",
+            body(serve(Method::HEAD, "/health").await.unwrap()).await
+        );
+        assert_eq!(
+            "update",
+            body(serve(Method::PATCH, "/api/users/1").await.unwrap()).await
+        );
+
+        let res = serve(Method::POST, "/health").await.unwrap();
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, res.status());
+        assert_eq!("GET, HEAD, OPTIONS", res.headers()["Allow"]);
+
+        assert_eq!(
+            "CONNECT, DELETE, GET, HEAD, PATCH, POST, PUT, TRACE, OPTIONS",
+            router.allowed("/echo/x", &Method::OPTIONS)
+        );
+        assert_eq!(
+            "PUT, OPTIONS",
+            router.allowed("/api/users/1", &Method::PATCH)
+        );
+    }
+
+    #[tokio::test]
+    async fn redirects() {
+        use crate::fs::Asset;
+        use crate::router::{full, Handler, Params, Router};
+        use http::{Request, Response, StatusCode};
+        use std::convert::Infallible;
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get(
+            "/oauth/callback",
+            Box::new(|_: Request<()>, _: Params| async {
+                Ok::<_, Infallible>(Response::new(full("")))
+            }),
+        );
+        router.post(
+            "/users/",
+            Box::new(|_: Request<()>, _: Params| async {
+                Ok::<_, Infallible>(Response::new(full("")))
+            }),
+        );
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("docs/index.html"), "docs").unwrap();
+        router.serve_files("/files/*filepath", dir.path());
+        router.serve_embedded(
+            "/assets/*filepath",
+            &[("docs/index.html", Asset::new(b"docs"))][..],
+        );
+
+        let redirect = |router: &Router<Handler<()>>, req: http::request::Builder| {
+            let res = router.serve_http(req.body(()).unwrap());
+            async move {
+                let res = res.await.unwrap();
+                let location = res.headers()["Location"].to_str().unwrap().to_string();
+                (res.status(), location)
+            }
+        };
+
+        let (code, location) = redirect(
+            &router,
+            Request::get("/oauth/callback/?code=abc&state=x%20y"),
+        )
+        .await;
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, code);
+        assert_eq!("/oauth/callback?code=abc&state=x%20y", location);
+        let (_, location) =
+            redirect(&router, Request::get("/OAuth/../oauth//Callback?code=abc")).await;
+        assert_eq!("/oauth/callback?code=abc", location);
+        let (code, location) = redirect(&router, Request::post("/users?page=2")).await;
+        assert_eq!(StatusCode::TEMPORARY_REDIRECT, code);
+        assert_eq!("/users/?page=2", location);
+        for path in ["/files/docs?page=2", "/assets/docs?page=2"] {
+            let (code, location) = redirect(&router, Request::get(path)).await;
+            assert_eq!(StatusCode::MOVED_PERMANENTLY, code);
+            assert_eq!(path.replace("?", "/?"), location);
+            let (code, _) = redirect(&router, Request::head(path)).await;
+            assert_eq!(StatusCode::TEMPORARY_REDIRECT, code);
+        }
+
+        router.permanent_redirects = true;
+        router.absolute_redirects = true;
+        let (code, location) = redirect(&router, Request::post("/users?page=2")).await;
+        assert_eq!(StatusCode::PERMANENT_REDIRECT, code);
+        assert_eq!("/users/?page=2", location);
+
+        let req = Request::get("/oauth/callback/?code=abc").header("host", "example.com:8080");
+        assert_eq!(
+            "http://example.com:8080/oauth/callback?code=abc",
+            redirect(&router, req).await.1
+        );
+        let req = Request::get("https://example.com/oauth/callback/");
+        assert_eq!(
+            "https://example.com/oauth/callback",
+            redirect(&router, req).await.1
+        );
+        let req = Request::get("/oauth/callback/")
+            .header("host", "backend:8080")
+            .header("x-forwarded-host", "example.com, proxy")
+            .header("x-forwarded-proto", "https");
+        assert_eq!(
+            "https://example.com/oauth/callback",
+            redirect(&router, req).await.1
+        );
+        let req = Request::get("/oauth/callback/")
+            .header("x-forwarded-host", "evil.com@example.com")
+            .header("x-forwarded-proto", "javascript");
+        assert_eq!("/oauth/callback", redirect(&router, req).await.1);
+
+        // directories served by the router follow its settings too
+        for path in ["/files/docs?page=2", "/assets/docs?page=2"] {
+            let req = Request::get(path).header("host", "example.com");
+            let (code, location) = redirect(&router, req).await;
+            assert_eq!(StatusCode::MOVED_PERMANENTLY, code);
+            assert_eq!(
+                format!("http://example.com{}", path.replace("?", "/?")),
+                location
+            );
+            let (code, _) = redirect(&router, Request::head(path)).await;
+            assert_eq!(StatusCode::PERMANENT_REDIRECT, code);
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_files() {
+        use crate::router::{Handler, Router};
+        use http::{Request, StatusCode};
+        use http_body_util::BodyExt;
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.serve_files("/examples/*filepath", "examples");
+
+        let index = std::fs::read("examples/send_file_index.html").unwrap();
+        let req = Request::get("/examples/send_file_index.html")
+            .body(())
+            .unwrap();
+        let res = router.serve_http(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("text/html", res.headers()["Content-Type"]);
+        assert_eq!(index, res.into_body().collect().await.unwrap().to_bytes());
+
+        let req = Request::head("/examples/send_file_index.html")
+            .body(())
+            .unwrap();
+        let res = router.serve_http(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(index.len().to_string(), res.headers()["Content-Length"]);
+
+        let req = Request::get("/examples/missing.html").body(()).unwrap();
+        let res = router.serve_http(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        for path in [
+            "/examples/../Cargo.toml",
+            "/examples/..%2fCargo.toml",
+            "/examples/%2e%2e/Cargo.toml",
+        ] {
+            router.decode_params = false;
+            let res = router
+                .serve_http(Request::get(path).body(()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", path);
+            router.decode_params = true;
+            let res = router
+                .serve_http(Request::get(path).body(()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", path);
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a b.txt", "100%.txt", "grüße.txt", "a%20b.txt"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+        let mut router: Router<Handler<()>> = Router::new();
+        router.serve_files("/files/*filepath", dir.path());
+        for decode_params in [false, true] {
+            router.decode_params = decode_params;
+            for (path, name) in [
+                ("/files/a%20b.txt", "a b.txt"),
+                ("/files/100%25.txt", "100%.txt"),
+                ("/files/gr%C3%BC%C3%9Fe.txt", "grüße.txt"),
+                ("/files/a%2520b.txt", "a%20b.txt"),
+            ] {
+                let res = router
+                    .serve_http(Request::get(path).body(()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(StatusCode::OK, res.status(), "{}", path);
+                assert_eq!(name, res.into_body().collect().await.unwrap().to_bytes());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_head() {
+        use crate::router::{full, Handler, Params, Router};
+        use http::{Request, Response, StatusCode};
+        use http_body_util::BodyExt;
+        use std::convert::Infallible;
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get(
+            "/health",
+            Box::new(|_: Request<()>, _: Params| async {
+                Ok::<_, Infallible>(Response::new(full("ok")))
+            }),
+        );
+        router.head(
+            "/explicit",
+            Box::new(|_: Request<()>, _: Params| async {
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .header("x-head", "1")
+                        .body(full(""))
+                        .unwrap(),
+                )
+            }),
+        );
+        router.get(
+            "/explicit",
+            Box::new(|_: Request<()>, _: Params| async {
+                Ok::<_, Infallible>(Response::new(full("get")))
+            }),
+        );
+
+        let head = |path: &str| router.serve_http(Request::head(path).body(()).unwrap());
+
+        // disabled by default
+        let res = head("/health").await.unwrap();
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, res.status());
+        assert_eq!("GET, OPTIONS", res.headers()["Allow"]);
+
+        router.handle_head = true;
+        let head = |path: &str| router.serve_http(Request::head(path).body(()).unwrap());
+        let res = head("/health").await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("2", res.headers()["Content-Length"]);
+        assert!(res
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes()
+            .is_empty());
+
+        let res = head("/explicit").await.unwrap();
+        assert_eq!("1", res.headers()["x-head"]);
+
+        let res = head("/health/").await.unwrap();
+        assert_eq!(StatusCode::TEMPORARY_REDIRECT, res.status());
+
+        assert_eq!(
+            "GET, HEAD, OPTIONS",
+            router.allowed("/health", &Method::POST)
+        );
+        assert_eq!(
+            "GET, HEAD, OPTIONS",
+            router.allowed("/explicit", &Method::POST)
+        );
+        assert_eq!("GET, HEAD, OPTIONS", router.allowed("*", &Method::OPTIONS));
+
+        // HEAD mirrors GET, not the route for any method
+        let mut router: Router<Handler<()>> = Router::new();
+        router.handle_head = true;
+        router.any(
+            "/proxy",
+            Box::new(|_: Request<()>, _: Params| async {
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .header("x-any", "1")
+                        .body(full("any"))
+                        .unwrap(),
+                )
+            }),
+        );
+        router.get(
+            "/proxy",
+            Box::new(|_: Request<()>, _: Params| async {
+                Ok::<_, Infallible>(Response::new(full("proxied")))
+            }),
+        );
+        let res = router
+            .serve_http(Request::head("/proxy").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!("7", res.headers()["Content-Length"]);
+        assert!(!res.headers().contains_key("x-any"));
+        let res = router
+            .serve_http(Request::post("/proxy").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!("1", res.headers()["x-any"]);
+    }
+
+    #[test]
+    fn group() {
+        use crate::router::Router;
+
         let mut router = Router::new();
+        router.get("/", "index");
+        {
+            let mut api = router.group("/api/");
+            assert_eq!("/api", api.prefix());
+            api.get("/", "api index");
+
+            let mut v1 = api.group("/v1");
+            v1.get("/users/:id", "show user");
+            v1.post("/users", "create user");
+            v1.get_named("user_posts", "/users/:id/posts", "user posts");
+        }
 
-        router.handle("GET", path, |_req: Request<Body>, _: Params| -> BoxFut {
-            Box::new(future::ok(Response::new(Body::from("test"))))
-        });
+        assert_eq!(
+            Ok("/api/v1/users/42/posts".to_string()),
+            router.url_for("user_posts", &[("id", "42")])
+        );
+
+        assert_eq!(Some(&"index"), router.lookup(&Method::GET, "/").0);
+        assert_eq!(Some(&"api index"), router.lookup(&Method::GET, "/api/").0);
+        assert_eq!(
+            Some(&"create user"),
+            router.lookup(&Method::POST, "/api/v1/users").0
+        );
+
+        let (handle, ps, _) = router.lookup(&Method::GET, "/api/v1/users/42");
+        assert_eq!(Some(&"show user"), handle);
+        assert_eq!(Some("42"), ps.by_name("id"));
+    }
+
+    #[test]
+    fn group_fallbacks() {
+        use crate::router::Router;
+
+        let mut router = Router::new();
+        router.not_found = Some("not found");
+        {
+            let mut api = router.group("/api");
+            api.not_found("api not found");
+            api.method_not_allowed("api method not allowed");
+
+            let mut user = api.group("/users/:id");
+            user.get("/profile", "profile");
+            user.not_found("user not found");
+        }
+
+        assert_eq!(Some(&"not found"), router.not_found_for("/other"));
+        assert_eq!(Some(&"not found"), router.not_found_for("/apis"));
+        assert_eq!(Some(&"api not found"), router.not_found_for("/api"));
+        assert_eq!(Some(&"api not found"), router.not_found_for("/api/unknown"));
+        assert_eq!(Some(&"api not found"), router.not_found_for("/api/users/"));
+        assert_eq!(
+            Some(&"user not found"),
+            router.not_found_for("/api/users/42/x")
+        );
+        assert_eq!(None, router.method_not_allowed_for("/other"));
+        assert_eq!(
+            Some(&"api method not allowed"),
+            router.method_not_allowed_for("/api/users/42/profile")
+        );
+    }
+
+    #[test]
+    fn remove() {
+        use crate::router::Router;
+        use crate::tree::InsertError;
+
+        let mut router = Router::new();
+        router.get("/user/:name", "get user");
+        router.post("/user/:name", "post user");
+
+        assert_eq!("GET, POST, OPTIONS", router.allowed("*", &Method::OPTIONS));
+        assert_eq!(
+            Some("post user"),
+            router.replace(Method::POST, "/user/:name", "new post user")
+        );
+        assert_eq!(
+            Some(&"new post user"),
+            router.lookup(&Method::POST, "/user/gordon").0
+        );
+
+        assert_eq!(Some("get user"), router.remove(&Method::GET, "/user/:name"));
+        assert_eq!(None, router.remove(&Method::GET, "/user/:name"));
+        assert_eq!(None, router.lookup(&Method::GET, "/user/gordon").0);
+        assert_eq!("POST, OPTIONS", router.allowed("*", &Method::OPTIONS));
+
+        assert_eq!(
+            Err(InsertError::MissingLeadingSlash {
+                path: "user/:name".to_string()
+            }),
+            router.try_replace(Method::POST, "user/:name", "post user")
+        );
+        assert!(router
+            .try_replace(Method::POST, "/user/:id", "post user")
+            .is_err());
+        assert_eq!(
+            Ok(Some("new post user")),
+            router.try_replace(Method::POST, "/user/:name", "post user")
+        );
+        assert_eq!(
+            Ok(None),
+            router.try_replace(Method::POST, "/users", "create user")
+        );
+        assert_eq!(
+            Some(&"post user"),
+            router.lookup(&Method::POST, "/user/gordon").0
+        );
+
+        // routes for any method are removed on their own
+        router.any("/proxy/*path", "proxy");
+        assert_eq!(None, router.remove(&Method::GET, "/proxy/*path"));
+        assert!(!router.allowed("/proxy/x", &Method::GET).is_empty());
+        assert_eq!(Some("proxy"), router.remove_any("/proxy/*path"));
+        assert_eq!(None, router.remove_any("/proxy/*path"));
+        assert_eq!(
+            "POST, OPTIONS",
+            router.allowed("/user/gordon", &Method::GET)
+        );
+        assert_eq!("", router.allowed("/proxy/x", &Method::GET));
+    }
+
+    #[test]
+    fn url_for() {
+        use crate::router::{Router, UrlError};
+        use crate::tree::InsertError;
+
+        let mut router = Router::new();
+        router.get_named("index", "/", ());
+        router.get_named("user", "/users/:id", ());
+        router.handle_named(Method::PUT, "user", "/users/:id", ());
+        router.get_named("user_file", "/users/:id/files/*filepath", ());
+        router.get_named("post", "/posts/:id<[0-9/]+>/:slug", ());
+
+        assert_eq!(
+            Err(InsertError::DuplicateName {
+                name: "user".to_string(),
+                path: "/users/:id".to_string()
+            }),
+            router.try_handle_named(Method::POST, "user", "/users", ())
+        );
+
+        assert_eq!(Ok("/".to_string()), router.url_for("index", &[]));
+        assert_eq!(
+            Ok("/users/42".to_string()),
+            router.url_for("user", &[("id", "42")])
+        );
+        assert_eq!(
+            Ok("/users/J%C3%BCrgen%2Fx".to_string()),
+            router.url_for("user", &[("id", "Jürgen/x")])
+        );
+        assert_eq!(
+            Ok("/users/42/files/a/b%3Fc.txt".to_string()),
+            router.url_for("user_file", &[("filepath", "/a/b?c.txt"), ("id", "42")])
+        );
+        assert_eq!(
+            Ok("/posts/1/hello".to_string()),
+            router.url_for("post", &[("id", "1"), ("slug", "hello")])
+        );
+        assert_eq!(
+            Err(UrlError::UnknownName {
+                name: "nope".to_string()
+            }),
+            router.url_for("nope", &[])
+        );
+        assert_eq!(
+            Err(UrlError::MissingParam {
+                name: "user".to_string(),
+                param: "id".to_string()
+            }),
+            router.url_for("user", &[("ids", "42")])
+        );
+        assert_eq!(
+            Err(UrlError::ExtraParam {
+                name: "user".to_string(),
+                param: "i".to_string()
+            }),
+            router.url_for("user", &[("id", "42"), ("i", "1")])
+        );
+        for (id, value) in [("abc", "abc"), ("1/2", "1%2F2")] {
+            assert_eq!(
+                Err(UrlError::InvalidParam {
+                    name: "post".to_string(),
+                    param: "id".to_string(),
+                    value: value.to_string()
+                }),
+                router.url_for("post", &[("id", id), ("slug", "hello")])
+            );
+        }
+
+        router.remove(&Method::GET, "/users/:id");
+        assert!(router.url_for("user", &[("id", "42")]).is_ok());
+        router.remove(&Method::PUT, "/users/:id");
+        assert!(router.url_for("user", &[("id", "42")]).is_err());
+    }
+
+    #[test]
+    fn routes() {
+        use crate::router::Router;
+
+        let mut router = Router::new();
+        router.get("/user/:name", 1);
+        router.post("/user/:name", 2);
+        router.get("/src/*filepath", 3);
+        router.any("/ping", 4);
+
+        let mut routes: Vec<(&str, String, &i32)> = router
+            .routes()
+            .map(|(method, path, handle)| (method.as_str(), path, handle))
+            .collect();
+        routes.sort();
+        let mut expected = vec![
+            ("GET", "/src/*filepath".to_string(), &3),
+            ("GET", "/user/:name".to_string(), &1),
+            ("POST", "/user/:name".to_string(), &2),
+        ];
+        for method in [
+            "CONNECT", "DELETE", "GET", "HEAD", "OPTIONS", "PATCH", "POST", "PUT", "TRACE",
+        ] {
+            expected.push((method, "/ping".to_string(), &4));
+        }
+        expected.sort();
+        assert_eq!(expected, routes);
+    }
+
+    #[test]
+    fn try_handle_invalid_path() {
+        use crate::router::Router;
+        use crate::tree::InsertError;
+
+        let mut router = Router::new();
+
+        assert_eq!(
+            Err(InsertError::MissingLeadingSlash {
+                path: "something".to_string()
+            }),
+            router.try_handle(Method::GET, "something", ())
+        );
+        assert_eq!(Ok(()), router.try_handle(Method::GET, "/user/:name", ()));
+        assert_eq!(
+            Err(InsertError::Duplicate {
+                path: "/user/:name".to_string()
+            }),
+            router.try_handle(Method::GET, "/user/:name", ())
+        );
+        assert!(router.lookup(&Method::GET, "/user/gordon").0.is_some());
+    }
+
+    #[test]
+    fn decode_params() {
+        use crate::router::Router;
+
+        let mut router = Router::new();
+        router.get("/users/:name", "user");
+        router.get("/files/:dir/*filepath", "file");
+
+        let (_, ps, _) = router.lookup(&Method::GET, "/users/J%C3%BCrgen");
+        assert_eq!(Some("J%C3%BCrgen"), ps.by_name("name"));
+
+        router.decode_params = true;
+
+        let (handle, ps, _) = router.lookup(&Method::GET, "/users/J%C3%BCrgen");
+        assert_eq!(Some(&"user"), handle);
+        assert_eq!(Some("Jürgen"), ps.by_name("name"));
+        assert_eq!(Some("J%C3%BCrgen"), ps.raw_by_name("name"));
+
+        // an encoded '/' does not separate segments
+        let (handle, ps, _) = router.lookup(&Method::GET, "/users/a%2Fb");
+        assert_eq!(Some(&"user"), handle);
+        assert_eq!(Some("a/b"), ps.by_name("name"));
+
+        let (handle, ps, _) = router.lookup(&Method::GET, "/files/my%20docs/a%2Fb/c%20d.txt");
+        assert_eq!(Some(&"file"), handle);
+        assert_eq!(Some("my docs"), ps.by_name("dir"));
+        assert_eq!(Some("/a/b/c d.txt"), ps.by_name("filepath"));
+        assert_eq!(Some("/a%2Fb/c%20d.txt"), ps.raw_by_name("filepath"));
+
+        // values without escapes have no separate raw value
+        let (_, ps, _) = router.lookup(&Method::GET, "/users/gordon");
+        assert_eq!(None, ps.0[0].raw);
+        assert_eq!(Some("gordon"), ps.raw_by_name("name"));
+    }
+
+    #[test]
+    fn lookup_ref() {
+        use crate::router::Router;
+
+        let mut router = Router::new();
+        router.get("/users/:name", "user");
+
+        let m = router.lookup_ref(&Method::GET, "/users/gordon");
+        assert_eq!(Some(&"user"), m.handle);
+        assert_eq!(Some("gordon"), m.params.by_name("name"));
+
+        assert!(router.lookup_ref(&Method::GET, "/users/gordon/").tsr);
+        assert!(router
+            .lookup_ref(&Method::POST, "/users/gordon")
+            .handle
+            .is_none());
     }
 }
@@ -0,0 +1,509 @@
+//! Serving a `Router` as a tower (or hyper) `Service`, and registering tower
+//! services as handlers.
+
+use crate::router::{
+    boxed, Body, BoxError, BoxFut, Handle, Handler, HandlerError, Params, ResponseFuture, Router,
+};
+use http::uri::PathAndQuery;
+use http::{Request, Response, Uri};
+use hyper::body::Incoming;
+use std::convert::Infallible;
+use std::fmt;
+use std::future::{self, Ready};
+use std::mem;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+impl<B> Router<Handler<B>> {
+    /// IntoService wraps the router into a `RouterService`, which can be
+    /// passed to hyper or wrapped in tower layers.
+    ///
+    /// ```rust
+    /// use radix_router::router::{Handler, Router};
+    /// use radix_router::service::RouterService;
+    ///
+    /// let router: Router<Handler> = Router::new();
+    /// let service: RouterService = router.into_service();
+    /// ```
+    pub fn into_service(self) -> RouterService<B> {
+        RouterService::new(Arc::new(self))
+    }
+
+    /// IntoMakeService wraps the router into a service returning a
+    /// `RouterService` for every connection.
+    pub fn into_make_service(self) -> IntoMakeService<B> {
+        self.into_service().into_make_service()
+    }
+}
+
+impl<B: Send + 'static> Router<Handler<B>> {
+    /// Mount serves every request below the prefix with the given router,
+    /// whatever its method, unless a route of this router matches.
+    ///
+    /// The mounted router sees the path with the prefix stripped, the original
+    /// URI is kept in the `OriginalUri` request extension. Params of the
+    /// prefix come first in the params passed to its handlers. Its own
+    /// NotFound and MethodNotAllowed handling applies.
+    ///
+    /// ```rust
+    /// use radix_router::router::{Handler, Router};
+    ///
+    /// let mut admin: Router<Handler> = Router::new();
+    /// // admin.get("/users", ...) serves /orgs/:org/admin/users
+    /// let mut router: Router<Handler> = Router::new();
+    /// router.mount("/orgs/:org/admin", admin);
+    /// ```
+    ///
+    /// Panics if the prefix does not begin with '/' or conflicts with another
+    /// mount.
+    pub fn mount(&mut self, prefix: &str, router: Router<Handler<B>>) {
+        self.mount_service(prefix, router.into_service());
+    }
+
+    /// MountService serves every request below the prefix with the given
+    /// tower `Service`, like `mount`. The params of the prefix are passed in
+    /// the request extensions.
+    pub fn mount_service<S, RB>(&mut self, prefix: &str, service: S)
+    where
+        S: Service<Request<B>, Response = Response<RB>> + Clone + Send + Sync + 'static,
+        S::Future: Send,
+        S::Error: Into<BoxError>,
+        RB: http_body::Body<Data = bytes::Bytes> + Send + 'static,
+        RB::Error: Into<BoxError>,
+    {
+        if !prefix.starts_with('/') {
+            panic!("prefix must begin with '/' in prefix '{}'", prefix);
+        }
+
+        let path = prefix.trim_end_matches('/').to_string() + "/*mount_path";
+        let handle = Mounted {
+            handle: from_service(service),
+        };
+        self.mounts.add_route(&path, Box::new(handle));
+    }
+
+    /// Unmount removes the router or service mounted at the prefix and
+    /// reports whether there was one.
+    pub fn unmount(&mut self, prefix: &str) -> bool {
+        let path = prefix.trim_end_matches('/').to_string() + "/*mount_path";
+        self.mounts.remove_route(&path).is_some()
+    }
+}
+
+/// OriginalUri is the URI of a request before the prefix of a mounted router
+/// or service was stripped from its path. It is added to the request
+/// extensions by the outermost mount.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OriginalUri(pub Uri);
+
+/// Mounted strips the mount prefix from the request path before calling the
+/// handle. The last param is the catch-all holding the rest of the path.
+struct Mounted<B> {
+    handle: Handler<B>,
+}
+
+impl<B> Handle<B> for Mounted<B> {
+    fn handle(&self, mut req: Request<B>, mut ps: Params) -> BoxFut {
+        let rest = match ps.0.pop() {
+            Some(param) => param.raw.unwrap_or(param.value),
+            None => String::new(),
+        };
+        let path = if rest.is_empty() { "/" } else { rest.as_str() };
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path.to_string(),
+        };
+
+        let mut parts = req.uri().clone().into_parts();
+        parts.path_and_query = match PathAndQuery::from_maybe_shared(path_and_query) {
+            Ok(path_and_query) => Some(path_and_query),
+            Err(err) => return Box::pin(future::ready(Err(err.into()))),
+        };
+        let uri = match Uri::from_parts(parts) {
+            Ok(uri) => uri,
+            Err(err) => return Box::pin(future::ready(Err(err.into()))),
+        };
+
+        let original = mem::replace(req.uri_mut(), uri);
+        if req.extensions().get::<OriginalUri>().is_none() {
+            req.extensions_mut().insert(OriginalUri(original));
+        }
+        self.handle.handle(req, ps)
+    }
+}
+
+/// RouterService serves requests with a shared `Router`. Cloning it only
+/// clones the `Arc`.
+pub struct RouterService<B = Incoming> {
+    router: Arc<Router<Handler<B>>>,
+}
+
+impl<B> RouterService<B> {
+    pub fn new(router: Arc<Router<Handler<B>>>) -> RouterService<B> {
+        RouterService { router }
+    }
+
+    /// Router returns the router serving the requests.
+    pub fn router(&self) -> &Arc<Router<Handler<B>>> {
+        &self.router
+    }
+
+    /// IntoMakeService wraps the service into a service returning a clone of
+    /// it for every connection.
+    pub fn into_make_service(self) -> IntoMakeService<B> {
+        IntoMakeService { service: self }
+    }
+}
+
+impl<B> Clone for RouterService<B> {
+    fn clone(&self) -> RouterService<B> {
+        RouterService {
+            router: self.router.clone(),
+        }
+    }
+}
+
+impl<B> fmt::Debug for RouterService<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RouterService").finish()
+    }
+}
+
+impl<B> From<Router<Handler<B>>> for RouterService<B> {
+    fn from(router: Router<Handler<B>>) -> RouterService<B> {
+        router.into_service()
+    }
+}
+
+impl<B> From<Arc<Router<Handler<B>>>> for RouterService<B> {
+    fn from(router: Arc<Router<Handler<B>>>) -> RouterService<B> {
+        RouterService::new(router)
+    }
+}
+
+impl<B> Service<Request<B>> for RouterService<B> {
+    type Response = Response<Body>;
+    type Error = HandlerError;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context) -> Poll<Result<(), HandlerError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> ResponseFuture {
+        self.router.serve_http(req)
+    }
+}
+
+impl<B> hyper::service::Service<Request<B>> for RouterService<B> {
+    type Response = Response<Body>;
+    type Error = HandlerError;
+    type Future = ResponseFuture;
+
+    fn call(&self, req: Request<B>) -> ResponseFuture {
+        self.router.serve_http(req)
+    }
+}
+
+/// IntoMakeService is a tower `MakeService` returning a `RouterService` for
+/// every target, e.g. every accepted connection.
+pub struct IntoMakeService<B = Incoming> {
+    service: RouterService<B>,
+}
+
+impl<B> Clone for IntoMakeService<B> {
+    fn clone(&self) -> IntoMakeService<B> {
+        IntoMakeService {
+            service: self.service.clone(),
+        }
+    }
+}
+
+impl<B> fmt::Debug for IntoMakeService<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntoMakeService").finish()
+    }
+}
+
+impl<B, T> Service<T> for IntoMakeService<B> {
+    type Response = RouterService<B>;
+    type Error = Infallible;
+    type Future = Ready<Result<RouterService<B>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _target: T) -> Self::Future {
+        future::ready(Ok(self.service.clone()))
+    }
+}
+
+/// ServiceHandler handles requests with a tower `Service`. The service is
+/// cloned for every request, and the route's `Params` are passed in the
+/// request extensions.
+#[derive(Clone, Debug)]
+pub struct ServiceHandler<S> {
+    service: S,
+}
+
+impl<S> ServiceHandler<S> {
+    pub fn new(service: S) -> ServiceHandler<S> {
+        ServiceHandler { service }
+    }
+}
+
+/// FromService returns a `Handler` calling the given tower `Service`.
+///
+/// ```rust
+/// use radix_router::router::{Handler, Router};
+/// use radix_router::service::from_service;
+///
+/// let mut router: Router<Handler> = Router::new();
+/// let mut admin: Router<Handler> = Router::new();
+/// // ...
+/// router.get("/admin/*path", from_service(admin.into_service()));
+/// ```
+pub fn from_service<S, B, RB>(service: S) -> Handler<B>
+where
+    S: Service<Request<B>, Response = Response<RB>> + Clone + Send + Sync + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    RB: http_body::Body<Data = bytes::Bytes> + Send + 'static,
+    RB::Error: Into<BoxError>,
+    B: Send + 'static,
+{
+    Box::new(ServiceHandler::new(service))
+}
+
+impl<S, B, RB> Handle<B> for ServiceHandler<S>
+where
+    S: Service<Request<B>, Response = Response<RB>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    RB: http_body::Body<Data = bytes::Bytes> + Send + 'static,
+    RB::Error: Into<BoxError>,
+    B: Send + 'static,
+{
+    fn handle(&self, mut req: Request<B>, ps: Params) -> BoxFut {
+        let mut service = self.service.clone();
+        req.extensions_mut().insert(ps);
+        Box::pin(async move {
+            future::poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .map_err(Into::into)?;
+            let res = service.call(req).await.map_err(Into::into)?;
+            Ok(res.map(boxed))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::router::{full, Body, BoxError, Handler, Params, Router};
+    use crate::service::{from_service, RouterService};
+    use http::{Method, Request, Response, StatusCode};
+    use http_body_util::BodyExt;
+    use std::convert::Infallible;
+    use tower::{Service, ServiceBuilder, ServiceExt};
+
+    async fn hello(_: Request<()>, ps: Params) -> Result<Response<Body>, Infallible> {
+        Ok(Response::new(full(format!("hello, {}", &ps[0]))))
+    }
+
+    async fn body(res: Response<Body>) -> String {
+        let bytes = res.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn router_service() {
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get("/hello/:name", Box::new(hello));
+
+        let service = ServiceBuilder::new()
+            .map_response(|mut res: Response<Body>| {
+                res.headers_mut().insert("x-layer", "1".parse().unwrap());
+                res
+            })
+            .service(router.into_service());
+
+        let req = Request::get("/hello/gordon").body(()).unwrap();
+        let res = service.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("1", res.headers()["x-layer"]);
+        assert_eq!("hello, gordon", body(res).await);
+
+        let req = Request::get("/nothing").body(()).unwrap();
+        let res = service.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn make_service() {
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get("/hello/:name", Box::new(hello));
+
+        let mut make_service = router.into_make_service();
+        let make = ServiceExt::<()>::ready(&mut make_service).await.unwrap();
+        let service: RouterService<()> = make.call(()).await.unwrap();
+        let req = Request::get("/hello/you").body(()).unwrap();
+        assert_eq!(
+            "hello, you",
+            body(service.oneshot(req).await.unwrap()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn service_handler() {
+        let echo_params = tower::service_fn(|req: Request<()>| async move {
+            let ps = req.extensions().get::<Params>().unwrap();
+            Ok::<_, BoxError>(Response::new(full(format!(
+                "{} {}",
+                req.uri().path(),
+                &ps[0]
+            ))))
+        });
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get("/files/*filepath", from_service(echo_params));
+
+        let req = Request::get("/files/a/b").body(()).unwrap();
+        let res = router.serve_http(req).await.unwrap();
+        assert_eq!("/files/a/b /a/b", body(res).await);
+    }
+
+    #[tokio::test]
+    async fn mount() {
+        use crate::service::OriginalUri;
+
+        let mut admin: Router<Handler<()>> = Router::new();
+        admin.get(
+            "/",
+            Box::new(|_: Request<()>, _: Params| async {
+                Ok::<_, Infallible>(Response::new(full("admin")))
+            }),
+        );
+        admin.get(
+            "/users/:id",
+            Box::new(|req: Request<()>, ps: Params| async move {
+                let original = &req.extensions().get::<OriginalUri>().unwrap().0;
+                let org = ps.by_name("org").unwrap();
+                let body = format!("{} {} {:?} {}", req.uri(), original, ps, org);
+                Ok::<_, Infallible>(Response::new(full(body)))
+            }),
+        );
+        admin.not_found = Some(Box::new(|_: Request<()>, _: Params| async {
+            Ok::<_, Infallible>(
+                Response::builder()
+                    .status(404)
+                    .body(full("admin not found"))
+                    .unwrap(),
+            )
+        }));
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.get(
+            "/orgs/:org/admin/special",
+            Box::new(|_: Request<()>, _: Params| async {
+                Ok::<_, Infallible>(Response::new(full("special")))
+            }),
+        );
+        router.mount("/orgs/:org/admin/", admin);
+
+        let serve = |method: &str, path: &str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(())
+                .unwrap();
+            router.serve_http(req)
+        };
+
+        let res = serve("GET", "/orgs/acme/admin/users/42?tab=posts")
+            .await
+            .unwrap();
+        assert_eq!(
+            "/users/42?tab=posts /orgs/acme/admin/users/42?tab=posts \
+             Params([Param { key: \"org\", value: \"acme\", raw: None }, \
+             Param { key: \"id\", value: \"42\", raw: None }]) acme",
+            body(res).await
+        );
+
+        assert_eq!(
+            "admin",
+            body(serve("GET", "/orgs/acme/admin").await.unwrap()).await
+        );
+        assert_eq!(
+            "admin",
+            body(serve("GET", "/orgs/acme/admin/").await.unwrap()).await
+        );
+        assert_eq!(
+            "special",
+            body(serve("GET", "/orgs/acme/admin/special").await.unwrap()).await
+        );
+
+        let res = serve("GET", "/orgs/acme/admin/nothing").await.unwrap();
+        assert_eq!("admin not found", body(res).await);
+
+        let res = serve("POST", "/orgs/acme/admin/users/42").await.unwrap();
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, res.status());
+        assert_eq!("GET, OPTIONS", res.headers()["Allow"]);
+
+        let res = serve("GET", "/orgs/acme/administrator").await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert_eq!("NOT_FOUND", body(res).await);
+
+        // redirects of the mounted router stay below the prefix
+        for path in [
+            "/orgs/acme/admin/users/42/?tab=posts",
+            "/orgs/acme/admin/USERS/42?tab=posts",
+        ] {
+            let res = serve("GET", path).await.unwrap();
+            assert_eq!(StatusCode::MOVED_PERMANENTLY, res.status(), "{}", path);
+            assert_eq!(
+                "/orgs/acme/admin/users/42?tab=posts",
+                res.headers()["Location"],
+                "{}",
+                path
+            );
+        }
+
+        // the mount is listed once per method
+        let routes: Vec<_> = router
+            .routes()
+            .filter(|(method, _, _)| **method == Method::GET)
+            .map(|(_, path, _)| path)
+            .collect();
+        assert_eq!(
+            vec!["/orgs/:org/admin/special", "/orgs/:org/admin/*mount_path"],
+            routes
+        );
+
+        assert!(router.unmount("/orgs/:org/admin"));
+        assert!(!router.unmount("/orgs/:org/admin"));
+        let res = router.serve_http(Request::get("/orgs/acme/admin/users/42").body(()).unwrap());
+        assert_eq!("NOT_FOUND", body(res.await.unwrap()).await);
+        let res = router.serve_http(Request::get("/orgs/acme/admin/special").body(()).unwrap());
+        assert_eq!("special", body(res.await.unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn mount_service() {
+        let metrics = tower::service_fn(|req: Request<()>| async move {
+            Ok::<_, BoxError>(Response::new(full(format!(
+                "{} {}",
+                req.method(),
+                req.uri()
+            ))))
+        });
+
+        let mut router: Router<Handler<()>> = Router::new();
+        router.mount_service("/metrics", metrics);
+
+        let req = Request::delete("/metrics/cpu").body(()).unwrap();
+        let res = router.serve_http(req).await.unwrap();
+        assert_eq!("DELETE /cpu", body(res).await);
+    }
+}
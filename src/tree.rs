@@ -1,5 +1,8 @@
-use crate::router::{Param, Params};
+use crate::router::{Params, ParamsRef};
+use regex::bytes::Regex;
 // use std::fmt::Debug;
+use std::error::Error;
+use std::fmt;
 use std::mem;
 use std::str;
 
@@ -12,11 +15,14 @@ fn min(a: usize, b: usize) -> usize {
 
 fn count_params(path: &[u8]) -> u8 {
     let mut n = 0;
-    for &c in path {
-        if c != b':' && c != b'*' {
+    let mut i = 0;
+    while i < path.len() {
+        if path[i] != b':' && path[i] != b'*' {
+            i += 1;
             continue;
         }
         n += 1;
+        i = wildcard_end(path, i);
     }
     if n > 255 {
         return 255;
@@ -24,6 +30,243 @@ fn count_params(path: &[u8]) -> u8 {
     n as u8
 }
 
+/// Returns the end of the wildcard starting at path[start], which is either
+/// the next '/' or the path end. A constraint like `:id<\d+>` is skipped as a
+/// whole, so it may contain any character.
+pub(crate) fn wildcard_end(path: &[u8], start: usize) -> usize {
+    let mut end = start + 1;
+    let mut depth = 0;
+    while end < path.len() && (depth > 0 || path[end] != b'/') {
+        match path[end] {
+            b'<' => depth += 1,
+            b'>' if depth > 0 => depth -= 1,
+            _ => {}
+        }
+        end += 1;
+    }
+    end
+}
+
+/// Splits a wildcard like `:id<\d+>` into its name and constraint.
+pub(crate) fn split_wildcard(wildcard: &[u8]) -> (&[u8], Option<&[u8]>) {
+    match wildcard.iter().position(|&c| c == b'<') {
+        Some(i) => (&wildcard[1..i], Some(&wildcard[i..])),
+        None => (&wildcard[1..], None),
+    }
+}
+
+/// Compiles the constraint of a param, e.g. `<\d+>`, into a regex matching
+/// the whole param value.
+pub(crate) fn compile_constraint(constraint: &[u8], full_path: &str) -> Result<Regex, InsertError> {
+    let invalid = |reason: String| InsertError::InvalidConstraint {
+        path: full_path.to_string(),
+        constraint: String::from_utf8_lossy(constraint).into_owned(),
+        reason,
+    };
+
+    if constraint.len() < 3 || constraint[constraint.len() - 1] != b'>' {
+        return Err(invalid(
+            "constraint must be a non-empty regex enclosed in '<' and '>'".to_string(),
+        ));
+    }
+
+    let pattern = str::from_utf8(&constraint[1..constraint.len() - 1])
+        .map_err(|err| invalid(err.to_string()))?;
+    Regex::new(&format!("^(?:{})$", pattern)).map_err(|err| invalid(err.to_string()))
+}
+
+/// Checks the wildcards of a route before anything is inserted, so that a
+/// malformed route never leaves a half-built branch behind.
+fn check_wildcards(path: &[u8], full_path: &str) -> Result<(), InsertError> {
+    let mut i = 0;
+    while i < path.len() {
+        let c = path[i];
+        if c != b':' && c != b'*' {
+            i += 1;
+            continue;
+        }
+
+        // find wildcard end (either '/' or path end)
+        let end = wildcard_end(path, i);
+        let (name, constraint) = split_wildcard(&path[i..end]);
+
+        // the wildcard name must not contain ':' and '*'
+        if name.iter().any(|&c| c == b':' || c == b'*') {
+            return Err(InsertError::TooManyWildcards {
+                path: full_path.to_string(),
+                segment: String::from_utf8_lossy(&path[i..]).into_owned(),
+            });
+        }
+
+        // check if the wildcard has a name
+        if name.is_empty() {
+            return Err(InsertError::EmptyWildcardName {
+                path: full_path.to_string(),
+            });
+        }
+
+        if let Some(constraint) = constraint {
+            if c == b'*' {
+                return Err(InsertError::InvalidConstraint {
+                    path: full_path.to_string(),
+                    constraint: String::from_utf8_lossy(constraint).into_owned(),
+                    reason: "catch-all parameters can not be constrained".to_string(),
+                });
+            }
+
+            compile_constraint(constraint, full_path)?;
+        }
+
+        if c == b'*' {
+            if end != path.len() {
+                return Err(InsertError::CatchAllNotAtEnd {
+                    path: full_path.to_string(),
+                });
+            }
+
+            // currently fixed width 1 for '/'
+            if i == 0 || path[i - 1] != b'/' {
+                return Err(InsertError::MissingSlashBeforeCatchAll {
+                    path: full_path.to_string(),
+                });
+            }
+        }
+
+        i = end;
+    }
+
+    Ok(())
+}
+
+/// InsertError is returned when a route can not be added to the tree.
+/// The tree is left unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertError {
+    /// The path does not begin with '/'.
+    MissingLeadingSlash { path: String },
+    /// A handle is already registered for the path.
+    Duplicate { path: String },
+    /// The wildcard `new` clashes with the `existing` wildcard registered at
+    /// the same position.
+    WildcardConflict {
+        path: String,
+        new: String,
+        existing: String,
+        prefix: String,
+    },
+    /// A path segment contains more than one wildcard.
+    TooManyWildcards { path: String, segment: String },
+    /// A wildcard without a name, e.g. `/user:` or `/src/*`.
+    EmptyWildcardName { path: String },
+    /// A catch-all is followed by further path segments.
+    CatchAllNotAtEnd { path: String },
+    /// A catch-all is not preceded by '/'.
+    MissingSlashBeforeCatchAll { path: String },
+    /// The route name is already used for the route `path`.
+    DuplicateName { name: String, path: String },
+    /// The constraint of a param, e.g. `:id<\d+>`, is not a valid regex.
+    InvalidConstraint {
+        path: String,
+        constraint: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InsertError::MissingLeadingSlash { path } => {
+                write!(f, "path must begin with '/' in path '{}'", path)
+            }
+            InsertError::Duplicate { path } => {
+                write!(f, "a handle is already registered for path '{}'", path)
+            }
+            InsertError::WildcardConflict {
+                path,
+                new,
+                existing,
+                prefix,
+            } => write!(
+                f,
+                "'{}' in new path '{}' conflicts with existing wildcard '{}' in existing prefix '{}'",
+                new, path, existing, prefix
+            ),
+            InsertError::TooManyWildcards { path, segment } => write!(
+                f,
+                "only one wildcard per path segment is allowed, has: '{}' in path '{}'",
+                segment, path
+            ),
+            InsertError::EmptyWildcardName { path } => write!(
+                f,
+                "wildcards must be named with a non-empty name in path '{}'",
+                path
+            ),
+            InsertError::CatchAllNotAtEnd { path } => write!(
+                f,
+                "catch-all routes are only allowed at the end of the path in path '{}'",
+                path
+            ),
+            InsertError::MissingSlashBeforeCatchAll { path } => {
+                write!(f, "no / before catch-all in path '{}'", path)
+            }
+            InsertError::DuplicateName { name, path } => write!(
+                f,
+                "route name '{}' is already registered for path '{}'",
+                name, path
+            ),
+            InsertError::InvalidConstraint {
+                path,
+                constraint,
+                reason,
+            } => write!(
+                f,
+                "invalid constraint '{}' in path '{}': {}",
+                constraint, path, reason
+            ),
+        }
+    }
+}
+
+impl Error for InsertError {}
+
+/// Match is the result of `Node::get_value_ref`.
+#[derive(Debug)]
+pub struct Match<'t, 'p, T> {
+    /// The handle registered for the path, if any.
+    pub handle: Option<&'t T>,
+    /// The values of the wildcards, keyed by their names in the tree.
+    pub params: ParamsRef<'t, 'p>,
+    /// Whether a handle exists for the path with an extra (without the)
+    /// trailing slash, if no handle was found.
+    pub tsr: bool,
+}
+
+/// LookupError is returned if a path given as bytes can not be looked up.
+/// It is a client error, the tree itself is fine.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LookupError {
+    /// The path is not valid UTF-8, starting at the given byte.
+    InvalidUtf8 { valid_up_to: usize },
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LookupError::InvalidUtf8 { valid_up_to } => {
+                write!(f, "path is not valid UTF-8 after byte {}", valid_up_to)
+            }
+        }
+    }
+}
+
+impl Error for LookupError {}
+
+fn check_utf8(path: &[u8]) -> Result<&str, LookupError> {
+    str::from_utf8(path).map_err(|err| LookupError::InvalidUtf8 {
+        valid_up_to: err.valid_up_to(),
+    })
+}
+
 #[derive(PartialEq, Clone, Debug, PartialOrd)]
 pub enum NodeType {
     Static,
@@ -42,6 +285,14 @@ pub struct Node<T> {
     children: Vec<Box<Node<T>>>,
     handle: Option<T>,
     priority: u32,
+    // regex the value of a param node must match
+    constraint: Option<Regex>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node::new()
+    }
 }
 
 impl<T> Node<T> {
@@ -55,10 +306,31 @@ impl<T> Node<T> {
             children: Vec::new(),
             handle: None,
             priority: 0,
+            constraint: None,
+        }
+    }
+
+    // The children of a node are stored in this order: the static children,
+    // in the same order as their first bytes in indices, then the param
+    // child if wild_child is set, then the catch-all child if there is one.
+
+    /// returns the position of the param child
+    fn param_pos(&self) -> Option<usize> {
+        if self.wild_child {
+            return Some(self.indices.len());
+        }
+        None
+    }
+
+    /// returns the position of the catch-all child
+    fn catch_all_pos(&self) -> Option<usize> {
+        match self.children.last() {
+            Some(child) if child.n_type == NodeType::CatchAll => Some(self.children.len() - 1),
+            _ => None,
         }
     }
 
-    /// increments priority of the given child and reorders if necessary
+    /// increments priority of the given static child and reorders if necessary
     fn increment_child_prio(&mut self, pos: usize) -> usize {
         self.children[pos].priority += 1;
         let prio = self.children[pos].priority;
@@ -78,7 +350,8 @@ impl<T> Node<T> {
                 &self.indices[pos..pos + 1], // the index char we move
                 &self.indices[new_pos..pos], // rest without char at 'pos'
                 &self.indices[pos + 1..],    // rest without char at 'pos'
-            ].concat();
+            ]
+            .concat();
         }
 
         new_pos
@@ -86,491 +359,621 @@ impl<T> Node<T> {
 
     /// addRoute adds a node with the given handle to the path.
     /// Not concurrency-safe!
+    ///
+    /// Panics if the route is malformed or conflicts with an existing one,
+    /// see `try_add_route` for a non-panicking variant.
     pub fn add_route(&mut self, path: &str, handle: T) {
-        let full_path = path.clone();
-        let path = path.as_ref();
-        self.priority += 1;
-        let num_params = count_params(path);
-
-        // non-empty tree
-        if self.path.len() > 0 || self.children.len() > 0 {
-            self.add_route_loop(num_params, path, full_path, handle);
-        } else {
-            // Empty tree
-            self.insert_child(num_params, path, full_path, handle);
-            self.n_type = NodeType::Root;
+        if let Err(err) = self.try_add_route(path, handle) {
+            panic!("{}", err);
         }
     }
 
-    fn add_route_loop(&mut self, num_params: u8, mut path: &[u8], full_path: &str, handle: T) {
-        // Update max_params of the current node
-        if num_params > self.max_params {
-            self.max_params = num_params;
-        }
-
-        // Find the longest common prefix.
-        // This also implies that the common prefix contains no ':' or '*'
-        // since the existing key can't contain those chars.
-        let mut i = 0;
-        let max = min(path.len(), self.path.len());
+    /// Like `add_route`, but returns an `InsertError` instead of panicking.
+    /// The tree is left unchanged if the route can not be added.
+    pub fn try_add_route(&mut self, path: &str, handle: T) -> Result<(), InsertError> {
+        let full_path = path;
+        let path = path.as_bytes();
+        check_wildcards(path, full_path)?;
+        let num_params = count_params(path);
 
-        while i < max && path[i] == self.path[i] {
-            i += 1;
-        }
+        self.check_conflicts(path, full_path)?;
+        self.add_route_loop(num_params, path, full_path, handle)?;
+        self.n_type = NodeType::Root;
+        self.priority += 1;
+        Ok(())
+    }
 
-        // Split edge
-        if i < self.path.len() {
-            let mut child = Node {
-                path: self.path[i..].to_vec(),
-                wild_child: self.wild_child,
-                n_type: NodeType::Static,
-                indices: self.indices.clone(),
-                children: Vec::new(),
-                handle: self.handle.take(),
-                priority: self.priority - 1,
+    /// Finds the conflicts of a route with the routes in the tree, following
+    /// the steps of add_route_loop without changing anything.
+    fn check_conflicts(&self, path: &[u8], full_path: &str) -> Result<(), InsertError> {
+        let path = if self.n_type == NodeType::Param {
+            &path[self.path.len()..]
+        } else {
+            // a new node, or a node split off an edge, has no routes below
+            if self.is_empty() && self.path.is_empty() {
+                return Ok(());
+            }
 
-                max_params: 0,
-            };
+            let mut i = path
+                .iter()
+                .zip(&self.path)
+                .take_while(|(a, b)| a == b)
+                .count();
+            if i < path.len() && path[i] == b'*' {
+                i -= 1;
+            }
+            if i < self.path.len() {
+                return Ok(());
+            }
+            &path[i..]
+        };
 
-            mem::swap(&mut self.children, &mut child.children);
+        if path.is_empty() {
+            if self.handle.is_some() {
+                return Err(InsertError::Duplicate {
+                    path: full_path.to_string(),
+                });
+            }
+            return Ok(());
+        }
 
-            // Update max_params (max of all children)
-            for c in &child.children {
-                if c.max_params > child.max_params {
-                    child.max_params = c.max_params;
+        if path[0] == b':' {
+            let end = wildcard_end(path, 0);
+            match self.param_pos() {
+                Some(pos) if self.children[pos].path != path[..end] => {
+                    Err(self.wildcard_conflict(pos, path, &path[..end], full_path))
                 }
+                Some(pos) => self.children[pos].check_conflicts(path, full_path),
+                None => Ok(()),
+            }
+        } else if path.starts_with(b"/*") {
+            match self.catch_all_pos() {
+                Some(pos) if self.children[pos].path == path => Err(InsertError::Duplicate {
+                    path: full_path.to_string(),
+                }),
+                Some(pos) => Err(self.wildcard_conflict(pos, path, path, full_path)),
+                None => Ok(()),
+            }
+        } else {
+            match self.indices.iter().position(|&index| index == path[0]) {
+                Some(pos) => self.children[pos].check_conflicts(path, full_path),
+                None => Ok(()),
             }
-
-            self.children = vec![Box::new(child)];
-            self.indices = vec![self.path[i]];
-            self.path = path[..i].to_vec();
-            self.wild_child = false;
         }
+    }
 
-        // Make new node a child of this node
-        if i < path.len() {
-            path = &path[i..];
-
-            if self.wild_child {
-                // *n = * {n}.children[0].clone();
-                return self.children[0].is_wild_child(num_params, path, full_path, handle);
+    // Priorities and max_params are only updated on the way back up, once the
+    // route has been inserted, so that a conflict leaves them untouched.
+    fn add_route_loop(
+        &mut self,
+        num_params: u8,
+        path: &[u8],
+        full_path: &str,
+        handle: T,
+    ) -> Result<(), InsertError> {
+        if self.n_type == NodeType::Param {
+            // the caller made sure that path starts with the param
+            let path = &path[self.path.len()..];
+            if path.is_empty() {
+                self.set_handle(full_path, handle)?;
+            } else {
+                self.add_child(num_params - 1, path, full_path, handle)?;
+            }
+        } else {
+            // a new node takes the path up to the first wildcard
+            if self.is_empty() && self.path.is_empty() {
+                self.path = path[..static_prefix_len(path)].to_vec();
             }
 
-            let c = path[0];
+            // Find the longest common prefix.
+            // This also implies that the common prefix contains no ':' or '*'
+            // since the existing key can't contain those chars.
+            let mut i = 0;
+            let max = min(path.len(), self.path.len());
 
-            // slash after param
-            if self.n_type == NodeType::Param && c == b'/' && self.children.len() == 1 {
-                self.children[0].priority += 1;
-                return self.children[0].add_route_loop(num_params, path, full_path, handle);
+            while i < max && path[i] == self.path[i] {
+                i += 1;
             }
 
-            // Check if a child with the next path byte exists
-            for mut i in 0..self.indices.len() {
-                if c == self.indices[i] {
-                    i = self.increment_child_prio(i);
-                    return self.children[i].add_route_loop(num_params, path, full_path, handle);
-                }
+            // a catch-all is stored below the node in front of its '/'
+            if i < path.len() && path[i] == b'*' {
+                i -= 1;
             }
 
-            // Otherwise insert it
-            if c != b':' && c != b'*' {
-                self.indices.push(c);
+            // Split edge
+            if i < self.path.len() {
+                let mut child = Node {
+                    path: self.path[i..].to_vec(),
+                    wild_child: self.wild_child,
+                    n_type: NodeType::Static,
+                    indices: self.indices.clone(),
+                    children: Vec::new(),
+                    handle: self.handle.take(),
+                    priority: self.priority,
+                    constraint: None,
+
+                    max_params: 0,
+                };
 
-                let len = self.indices.len();
+                mem::swap(&mut self.children, &mut child.children);
 
-                let child: Box<Node<T>> = Box::new(Node {
-                    path: Vec::new(),
+                // Update max_params (max of all children)
+                for c in &child.children {
+                    if c.max_params > child.max_params {
+                        child.max_params = c.max_params;
+                    }
+                }
 
-                    wild_child: false,
+                self.children = vec![Box::new(child)];
+                self.indices = vec![self.path[i]];
+                self.path = path[..i].to_vec();
+                self.wild_child = false;
+            }
 
-                    n_type: NodeType::Static,
+            if i < path.len() {
+                // Make new node a child of this node
+                self.add_child(num_params, &path[i..], full_path, handle)?;
+            } else {
+                // Make node a (in-path) leaf
+                self.set_handle(full_path, handle)?;
+            }
+        }
 
-                    max_params: num_params,
+        // Update max_params of the current node
+        if num_params > self.max_params {
+            self.max_params = num_params;
+        }
 
-                    indices: Vec::new(),
+        Ok(())
+    }
 
-                    children: Vec::new(),
+    fn set_handle(&mut self, full_path: &str, handle: T) -> Result<(), InsertError> {
+        if self.handle.is_some() {
+            return Err(InsertError::Duplicate {
+                path: full_path.to_string(),
+            });
+        }
 
-                    handle: None,
+        self.handle = Some(handle);
+        Ok(())
+    }
 
-                    priority: 0,
-                });
+    /// inserts the rest of a route below this node, path starts with either
+    /// a static part, a param or the '/' of a catch-all
+    fn add_child(
+        &mut self,
+        num_params: u8,
+        path: &[u8],
+        full_path: &str,
+        handle: T,
+    ) -> Result<(), InsertError> {
+        if path[0] == b':' {
+            // Param
+            let end = wildcard_end(path, 0);
+
+            if let Some(pos) = self.param_pos() {
+                // the param must be the same, e.g. not :name and :names
+                if self.children[pos].path != path[..end] {
+                    return Err(self.wildcard_conflict(pos, path, &path[..end], full_path));
+                }
+
+                self.children[pos].add_route_loop(num_params, path, full_path, handle)?;
+                self.children[pos].priority += 1;
+            } else {
+                let constraint = match split_wildcard(&path[..end]).1 {
+                    Some(constraint) => Some(compile_constraint(constraint, full_path)?),
+                    None => None,
+                };
 
-                self.children.push(child);
+                let mut child = Box::new(Node {
+                    path: path[..end].to_vec(),
+                    n_type: NodeType::Param,
+                    constraint,
+                    ..Node::new()
+                });
 
-                let i = self.increment_child_prio(len - 1);
+                child.add_route_loop(num_params, path, full_path, handle)?;
+                child.priority += 1;
 
-                return self.children[i].insert_child(num_params, path, full_path, handle);
+                self.children.insert(self.indices.len(), child);
+                self.wild_child = true;
             }
-
-            return self.insert_child(num_params, path, full_path, handle);
-        } else if i == path.len() {
-            // Make node a (in-path) leaf
-            if self.handle.is_some() {
-                panic!("a handle is already registered for path '{}'", full_path);
+        } else if path.starts_with(b"/*") {
+            // CatchAll
+            if let Some(pos) = self.catch_all_pos() {
+                if self.children[pos].path == path {
+                    return Err(InsertError::Duplicate {
+                        path: full_path.to_string(),
+                    });
+                }
+                return Err(self.wildcard_conflict(pos, path, path, full_path));
             }
 
-            self.handle = Some(handle);
+            self.children.push(Box::new(Node {
+                path: path.to_vec(),
+                n_type: NodeType::CatchAll,
+                max_params: 1,
+                handle: Some(handle),
+                priority: 1,
+                ..Node::new()
+            }));
+        } else {
+            // Check if a child with the next path byte exists
+            let c = path[0];
+            if let Some(pos) = self.indices.iter().position(|&index| index == c) {
+                self.children[pos].add_route_loop(num_params, path, full_path, handle)?;
+                self.increment_child_prio(pos);
+            } else {
+                // Otherwise insert it
+                let mut child: Box<Node<T>> = Box::default();
+                child.add_route_loop(num_params, path, full_path, handle)?;
+
+                let pos = self.indices.len();
+                self.indices.push(c);
+                self.children.insert(pos, child);
+                self.increment_child_prio(pos);
+            }
         }
 
-        return;
+        Ok(())
     }
 
-    fn is_wild_child(&mut self, mut num_params: u8, path: &[u8], full_path: &str, handle: T) {
-        self.priority += 1;
+    fn wildcard_conflict(
+        &self,
+        pos: usize,
+        path: &[u8],
+        new: &[u8],
+        full_path: &str,
+    ) -> InsertError {
+        let existing = &self.children[pos].path;
+
+        // path is always a suffix of full_path
+        let prefix = [
+            &full_path.as_bytes()[..full_path.len() - path.len()],
+            existing,
+        ]
+        .concat();
+
+        InsertError::WildcardConflict {
+            path: full_path.to_string(),
+            new: String::from_utf8_lossy(new).into_owned(),
+            existing: String::from_utf8_lossy(existing).into_owned(),
+            prefix: String::from_utf8_lossy(&prefix).into_owned(),
+        }
+    }
 
-        // Update maxParams of the child node
+    /// Reports whether no handle is registered in the tree.
+    pub fn is_empty(&self) -> bool {
+        self.handle.is_none() && self.children.is_empty()
+    }
 
-        if num_params > self.max_params {
-            self.max_params = num_params;
+    /// Registers the handle for the given route like `add_route`. If a handle
+    /// is already registered for exactly this route it is replaced and the
+    /// old handle is returned instead of panicking.
+    ///
+    /// Panics if the route is invalid or conflicts with another route, see
+    /// `try_replace_route`.
+    pub fn replace_route(&mut self, path: &str, handle: T) -> Option<T> {
+        match self.try_replace_route(path, handle) {
+            Ok(old) => old,
+            Err(err) => panic!("{}", err),
         }
+    }
 
-        num_params -= 1;
-
-        // Check if the wildcard matches
+    /// Like `replace_route`, but returns an `InsertError` instead of
+    /// panicking.
+    pub fn try_replace_route(&mut self, path: &str, handle: T) -> Result<Option<T>, InsertError> {
+        if let Some(node) = self.find_route_mut(path.as_bytes()) {
+            if node.handle.is_some() {
+                return Ok(node.handle.replace(handle));
+            }
+        }
 
-        if path.len() >= self.path.len()
-            && self.path == &path[..self.path.len()]
-            // Check for longer wildcard, e.g. :name and :names
-            && (self.path.len() >= path.len() || path[self.path.len()] == b'/')
-        {
-            self.add_route_loop(num_params, path, full_path, handle);
-        } else {
-            // Wildcard conflict
-            let path_seg = if self.n_type == NodeType::CatchAll {
-                str::from_utf8(path).unwrap()
-            } else {
-                str::from_utf8(path)
-                    .unwrap()
-                    .splitn(2, '/')
-                    .into_iter()
-                    .next()
-                    .unwrap()
-            };
+        self.try_add_route(path, handle)?;
+        Ok(None)
+    }
 
-            let prefix = [
-                &full_path[..full_path.find(path_seg).unwrap()],
-                str::from_utf8(&self.path).unwrap(),
-            ].concat();
+    /// Removes the given route (e.g. "/user/:name") from the tree and returns
+    /// its handle. Nodes left without a handle are pruned or merged back into
+    /// their parent, so the tree looks as if the route was never added.
+    pub fn remove_route(&mut self, path: &str) -> Option<T> {
+        let handle = self.remove_route_loop(path.as_bytes());
 
-            panic!("'{}' in new path '{}' conflicts with existing wildcard '{}' in existing prefix '{}'", path_seg, full_path, str::from_utf8(&self.path).unwrap(), prefix);
+        if handle.is_some() && self.is_empty() {
+            *self = Node::new();
         }
+
+        handle
     }
 
-    fn insert_child(&mut self, num_params: u8, path: &[u8], full_path: &str, handle: T) {
-        self.insert_child_loop(0, 0, num_params, path, full_path, handle);
+    /// Returns an iterator over all registered routes and their handles,
+    /// e.g. ("/user/:name", &handle).
+    pub fn routes(&self) -> Routes<'_, T> {
+        Routes {
+            stack: vec![(self, Vec::new())],
+        }
     }
 
-    fn insert_child_loop(
-        &mut self,
-        mut offset: usize,
-        mut i: usize,
-        mut num_params: u8,
-        path: &[u8],
-        full_path: &str,
-        handle: T,
-    ) {
-        if num_params > 0 {
-            let max = path.len();
-            let c = path[i];
-
-            // find prefix until first wildcard (beginning with ':'' or '*'')
-            if c != b':' && c != b'*' {
-                return self.insert_child_loop(offset, i + 1, num_params, path, full_path, handle);
-            }
+    /// Reports whether a handle is registered for exactly the given route
+    /// (e.g. "/user/:name").
+    pub fn has_route(&self, path: &str) -> bool {
+        let mut node = self;
+        let mut path = path.as_bytes();
 
-            // find wildcard end (either '/' or path end)
-            let mut end = i + 1;
-            while end < max && path[end] != b'/' {
-                match path[end] {
-                    // the wildcard name must not contain ':' and '*'
-                    b':' | b'*' => panic!(
-                        "only one wildcard per path segment is allowed, has: '{}' in path '{}'",
-                        str::from_utf8(&path[i..]).unwrap(),
-                        full_path
-                    ),
-                    _ => end += 1,
-                }
+        loop {
+            if !path.starts_with(&node.path) {
+                return false;
             }
 
-            // println!("self path: {}", str::from_utf8(&self.path).unwrap());
-            // println!("temp path: {}", str::from_utf8(path).unwrap());
-            // println!("self {:?}", self.children[0]);
-            // println!("self {:?}", self.children.len());
-
-            // check if this Node existing children which would be
-            // unreachable if we insert the wildcard here
-            if self.children.len() > 0 {
-                panic!(
-                    "wildcard route '{}' conflicts with existing children in path '{}'",
-                    str::from_utf8(&path[i..end]).unwrap(),
-                    full_path
-                )
+            path = &path[node.path.len()..];
+            if path.is_empty() {
+                return node.handle.is_some();
             }
 
-            // check if the wildcard has a name
-            if end - i < 2 {
-                panic!(
-                    "wildcards must be named with a non-empty name in path '{}'",
-                    full_path
-                );
+            match node.child_pos(path) {
+                Some(pos) => node = &node.children[pos],
+                None => return false,
             }
+        }
+    }
 
-            if c == b':' {
-                // Param
-                // split path at the beginning of the wildcard
-                if i > 0 {
-                    self.path = path[offset..i].to_vec();
-                    offset = i;
-                }
+    /// walks the tree along the literal route, wildcards included
+    fn find_route_mut(&mut self, path: &[u8]) -> Option<&mut Node<T>> {
+        if !path.starts_with(&self.path) {
+            return None;
+        }
 
-                let child = Box::new(Node {
-                    path: Vec::new(),
-                    wild_child: false,
-                    n_type: NodeType::Param,
-                    max_params: num_params,
-                    indices: Vec::new(),
-                    children: Vec::new(),
-                    handle: None,
-                    priority: 0,
-                });
+        let path = &path[self.path.len()..];
+        if path.is_empty() {
+            return Some(self);
+        }
 
-                self.children = vec![child];
-                self.wild_child = true;
+        let pos = self.child_pos(path)?;
+        self.children[pos].find_route_mut(path)
+    }
 
-                self.children[0].priority += 1;
-                num_params -= 1;
-
-                if end < max {
-                    self.children[0].path = path[offset..end].to_vec();
-                    offset = end;
-
-                    let child: Box<Node<T>> = Box::new(Node {
-                        path: Vec::new(),
-                        wild_child: false,
-                        n_type: NodeType::Static,
-                        max_params: num_params,
-                        indices: Vec::new(),
-                        children: Vec::new(),
-                        handle: None,
-                        priority: 1,
-                    });
+    /// returns the position of the child the literal route path is stored in
+    fn child_pos(&self, path: &[u8]) -> Option<usize> {
+        if path[0] == b':' {
+            self.param_pos()
+        } else if path.starts_with(b"/*") {
+            self.catch_all_pos()
+        } else {
+            self.indices.iter().position(|&index| index == path[0])
+        }
+    }
 
-                    self.children[0].children.push(child);
-                    self.children[0].children[0].insert_child_loop(
-                        offset,
-                        i + 1,
-                        num_params,
-                        path,
-                        full_path,
-                        handle,
-                    );
-                } else {
-                    self.children[0].insert_child_loop(
-                        offset,
-                        i + 1,
-                        num_params,
-                        path,
-                        full_path,
-                        handle,
-                    );
-                }
-            } else {
-                // CatchAll
-                if end != max || num_params > 1 {
-                    panic!(
-                        "catch-all routes are only allowed at the end of the path in path '{}'",
-                        full_path
-                    );
-                }
+    fn remove_route_loop(&mut self, path: &[u8]) -> Option<T> {
+        if !path.starts_with(&self.path) {
+            return None;
+        }
 
-                if self.path.len() > 0 && self.path[self.path.len() - 1] == b'/' {
-                    panic!(
-                        "catch-all conflicts with existing handle for the path segment root in path '{}'", 
-                        full_path
-                    );
+        let path = &path[self.path.len()..];
+        let handle = if path.is_empty() {
+            self.handle.take()?
+        } else {
+            let pos = self.child_pos(path)?;
+            let handle = self.children[pos].remove_route_loop(path)?;
+            let is_static = pos < self.indices.len();
+
+            if self.children[pos].is_empty() {
+                let child = self.children.remove(pos);
+                if is_static {
+                    self.indices.remove(pos);
+                } else if child.n_type == NodeType::Param {
+                    self.wild_child = false;
                 }
+            } else if is_static {
+                self.decrement_child_prio(pos);
+            }
 
-                // currently fixed width 1 for '/'
-                i -= 1;
-                if path[i] != b'/' {
-                    panic!("no / before catch-all in path '{}'", full_path);
-                }
+            handle
+        };
 
-                self.path = path[offset..i].to_vec();
+        self.priority -= 1;
+        self.merge_child();
+        self.update_max_params();
 
-                // first node: catchAll node with empty path
-                let child = Box::new(Node {
-                    path: Vec::new(),
-                    wild_child: true,
-                    n_type: NodeType::CatchAll,
-                    max_params: 1,
-                    indices: Vec::new(),
-                    children: Vec::new(),
-                    handle: None,
-                    priority: 0,
-                });
+        Some(handle)
+    }
 
-                self.children = vec![child];
+    /// moves the static child at the given position back after its priority
+    /// dropped
+    fn decrement_child_prio(&mut self, pos: usize) {
+        let prio = self.children[pos].priority;
+        let mut new_pos = pos;
 
-                self.indices = vec![path[i]];
+        while new_pos + 1 < self.indices.len() && self.children[new_pos + 1].priority > prio {
+            // swap node positions
+            self.children.swap(new_pos, new_pos + 1);
+            self.indices.swap(new_pos, new_pos + 1);
+            new_pos += 1;
+        }
+    }
 
-                self.children[0].priority += 1;
+    /// merges a static node without a handle with its only static child, the
+    /// reverse of splitting an edge in add_route_loop
+    fn merge_child(&mut self) {
+        if self.handle.is_some()
+            || self.children.len() != 1
+            || self.indices.len() != 1
+            || self.n_type > NodeType::Root
+        {
+            return;
+        }
 
-                // second node: node holding the variable
-                let child: Box<Node<T>> = Box::new(Node {
-                    path: path[i..].to_vec(),
-                    wild_child: false,
-                    n_type: NodeType::CatchAll,
-                    max_params: 1,
-                    indices: Vec::new(),
-                    children: Vec::new(),
-                    handle: Some(handle),
-                    priority: 1,
-                });
+        let child = *self.children.remove(0);
+        self.path.extend_from_slice(&child.path);
+        self.wild_child = child.wild_child;
+        self.indices = child.indices;
+        self.children = child.children;
+        self.handle = child.handle;
+    }
 
-                self.children[0].children.push(child);
+    /// recomputes max_params from the children
+    fn update_max_params(&mut self) {
+        let mut max_params = self
+            .children
+            .iter()
+            .map(|child| child.max_params)
+            .max()
+            .unwrap_or(0);
 
-                return;
-            }
-        } else {
-            // insert remaining path part and handle to the leaf
-            self.path = path[offset..].to_vec();
-            self.handle = Some(handle);
+        if self.n_type > NodeType::Root && !self.wild_child {
+            max_params += 1;
         }
+
+        self.max_params = max_params;
     }
 
     /// Returns the handle registered with the given path (key). The values of
     /// wildcards are saved to a map.
+    /// Static segments take precedence over params, which take precedence
+    /// over catch-alls. If a branch dead-ends, the lookup backtracks and tries
+    /// the next one.
     /// If no handle can be found, a TSR (trailing slash redirect) recommendation is
     /// made if a handle exists with an extra (without the) trailing slash for the
     /// given path.
     pub fn get_value(&self, path: &str) -> (Option<&T>, Params, bool) {
-        // let mut handle = None;
-        self.get_value_loop(path.as_ref(), Params::new())
-    }
-
-    /// outer loop for walking the tree
-    fn get_value_loop(&self, mut path: &[u8], p: Params) -> (Option<&T>, Params, bool) {
-        if path.len() > self.path.len() {
-            if self.path == &path[..self.path.len()] {
-                path = &path[self.path.len()..];
-                // If this node does not have a wildcard (param or catchAll)
-                // child,  we can just look up the next child node and continue
-                // to walk down the tree
-                if !self.wild_child {
-                    let c = path[0];
-                    for i in 0..self.indices.len() {
-                        if c == self.indices[i] {
-                            return self.children[i].get_value_loop(path, p);
-                        }
-                    }
-                    // Nothing found.
-                    // We can recommend to redirect to the same URL without a
-                    // trailing slash if a leaf exists for that path.
-                    let tsr = path == [b'/'] && self.handle.is_some();
-                    return (None, p, tsr);
-                }
-
-                // handle wildcard child
-                return self.children[0].handle_wildcard_child(path, p);
-            }
-        } else if self.path == path {
-            // We should have reached the node containing the handle.
-            // Check if this node has a handle registered.
-            if self.handle.is_some() {
-                return (self.handle.as_ref(), p, false);
-            }
-
-            if path == [b'/'] && self.wild_child && self.n_type != NodeType::Root {
-                // tsr = true;
-                return (self.handle.as_ref(), p, true);
-            }
-
-            // No handle found. Check if a handle for this path + a
-            // trailing slash exists for trailing slash recommendation
-            for i in 0..self.indices.len() {
-                if self.indices[i] == b'/' {
-                    let tsr = (self.path.len() == 1 && self.children[i].handle.is_some())
-                        || (self.children[i].n_type == NodeType::CatchAll
-                            && self.children[i].children[0].handle.is_some());
-                    return (self.handle.as_ref(), p, tsr);
-                }
-            }
+        let m = self.get_value_ref(path);
+        (m.handle, m.params.into(), m.tsr)
+    }
 
-            return (self.handle.as_ref(), p, false);
+    /// Like `get_value`, but the params borrow their keys from the tree and
+    /// their values from the path instead of being copied.
+    pub fn get_value_ref<'t, 'p>(&'t self, path: &'p str) -> Match<'t, 'p, T> {
+        let mut params = ParamsRef::new();
+        if let Some(handle) = self.get_value_loop(path, 0, &mut params) {
+            return Match {
+                handle: Some(handle),
+                params,
+                tsr: false,
+            };
         }
 
         // Nothing found. We can recommend to redirect to the same URL with an
-        // extra trailing slash if a leaf exists for that path
-        let tsr = (path == [b'/'])
-            || (self.path.len() == path.len() + 1
-                && self.path[path.len()] == b'/'
-                && path == &self.path[..self.path.len() - 1]
-                && self.handle.is_some());
+        // extra (without the) trailing slash if a leaf exists for that path.
+        let tsr = match path.strip_suffix('/') {
+            Some(stripped) => !stripped.is_empty() && self.has_value(stripped),
+            None => self.has_value(&format!("{}/", path)),
+        };
 
-        return (None, p, tsr);
+        Match {
+            handle: None,
+            params: ParamsRef::new(),
+            tsr,
+        }
     }
 
-    fn handle_wildcard_child(&self, mut path: &[u8], mut p: Params) -> (Option<&T>, Params, bool) {
-        match self.n_type {
+    /// Reports whether a handle is registered for a route matching the path,
+    /// without collecting the params.
+    pub fn has_value(&self, path: &str) -> bool {
+        self.get_value_loop(path, 0, &mut ParamsRef::new())
+            .is_some()
+    }
+
+    /// Like `get_value`, but for a path which is not known to be valid UTF-8,
+    /// e.g. the raw bytes of a request line. Invalid paths are rejected with
+    /// a `LookupError` instead of being matched.
+    pub fn get_value_bytes(&self, path: &[u8]) -> Result<(Option<&T>, Params, bool), LookupError> {
+        Ok(self.get_value(check_utf8(path)?))
+    }
+
+    /// recursive function for walking the tree, starting at path[pos..]. The
+    /// params of a branch that dead-ends are removed again.
+    fn get_value_loop<'t, 'p>(
+        &'t self,
+        path: &'p str,
+        pos: usize,
+        p: &mut ParamsRef<'t, 'p>,
+    ) -> Option<&'t T> {
+        let rest = &path.as_bytes()[pos..];
+
+        match self.n_type {
             NodeType::Param => {
                 // find param end (either '/' or path end)
-                let mut end = 0;
-                while end < path.len() && path[end] != b'/' {
-                    end += 1;
+                let end = rest.iter().position(|&c| c == b'/').unwrap_or(rest.len());
+                // pos follows a complete rune of the static prefix and end is
+                // at a '/' or the end of the path
+                let value = &path[pos..pos + end];
+
+                // check the value against the constraint of the param
+                if let Some(ref constraint) = self.constraint {
+                    if !constraint.is_match(value.as_bytes()) {
+                        return None;
+                    }
                 }
 
                 // save param value
                 if p.is_empty() {
-                    // lazy allocation
-                    p = Params(Vec::with_capacity(self.max_params as usize));
+                    // allocates only if the params don't fit inline
+                    p.reserve(self.max_params as usize);
                 }
 
-                p.push(Param {
-                    key: String::from_utf8(self.path[1..].to_vec()).unwrap(),
-                    value: String::from_utf8(path[..end].to_vec()).unwrap(),
-                });
+                let key = str::from_utf8(split_wildcard(&self.path).0)
+                    .expect("param names are valid UTF-8");
+                p.push(key, value);
 
                 // we need to go deeper!
-                if end < path.len() {
-                    if self.children.len() > 0 {
-                        path = &path[end..];
+                let handle = if end < rest.len() {
+                    self.get_child_value(path, pos + end, p)
+                } else {
+                    self.handle.as_ref()
+                };
 
-                        return self.children[0].get_value_loop(path, p);
-                    }
+                if handle.is_none() {
+                    p.pop();
+                }
+                handle
+            }
+            NodeType::CatchAll => {
+                // save param value
+                let key = str::from_utf8(&self.path[2..]).expect("catch-all names are valid UTF-8");
+                p.push(key, &path[pos..]);
 
-                    // ... but we can't
-                    let tsr = path.len() == end + 1;
-                    return (None, p, tsr);
+                self.handle.as_ref()
+            }
+            _ => {
+                if !rest.starts_with(&self.path) {
+                    return None;
                 }
 
-                if self.handle.is_some() {
-                    return (self.handle.as_ref(), p, false);
-                } else if self.children.len() == 1 {
-                    // No handle found. Check if a handle for this path + a
-                    // trailing slash exists for TSR recommendation
-                    let tsr = self.children[0].path == &[b'/'] && self.children[0].handle.is_some();
-                    return (None, p, tsr);
+                let pos = pos + self.path.len();
+                if pos == path.len() {
+                    // We should have reached the node containing the handle.
+                    return self.handle.as_ref();
                 }
 
-                return (None, p, false);
+                self.get_child_value(path, pos, p)
             }
-            NodeType::CatchAll => {
-                // save param value
-                if p.is_empty() {
-                    // lazy allocation
-                    p = Params(Vec::with_capacity(self.max_params as usize));
-                }
+        }
+    }
 
-                p.push(Param {
-                    key: String::from_utf8(self.path[2..].to_vec()).unwrap(),
-                    value: String::from_utf8(path.to_vec()).unwrap(),
-                });
+    /// tries the static child, the param child and the catch-all child in
+    /// this order
+    fn get_child_value<'t, 'p>(
+        &'t self,
+        path: &'p str,
+        pos: usize,
+        p: &mut ParamsRef<'t, 'p>,
+    ) -> Option<&'t T> {
+        let c = path.as_bytes()[pos];
+
+        if let Some(i) = self.indices.iter().position(|&index| index == c) {
+            if let Some(handle) = self.children[i].get_value_loop(path, pos, p) {
+                return Some(handle);
+            }
+        }
 
-                return (self.handle.as_ref(), p, false);
+        if let Some(i) = self.param_pos() {
+            if let Some(handle) = self.children[i].get_value_loop(path, pos, p) {
+                return Some(handle);
             }
-            _ => panic!("invalid node type"),
+        }
+
+        match self.catch_all_pos() {
+            Some(i) if c == b'/' => self.children[i].get_value_loop(path, pos, p),
+            _ => None,
         }
     }
 
@@ -584,270 +987,184 @@ impl<T> Node<T> {
         fix_trailing_slash: bool,
     ) -> (String, bool) {
         let mut ci_path = Vec::with_capacity(path.len() + 1);
-        let found = self.find_case_insensitive_path_rec(
-            path.as_bytes(),
-            path.to_ascii_lowercase().as_bytes(),
-            &mut ci_path,
-            [0; 4],
-            fix_trailing_slash,
-        );
-        (String::from_utf8(ci_path).unwrap(), found)
+        let mut found = self.find_case_insensitive_path_rec(path, &[], &mut ci_path);
+
+        if !found && fix_trailing_slash {
+            found = match path.strip_suffix('/') {
+                Some(stripped) => {
+                    !stripped.is_empty()
+                        && self.find_case_insensitive_path_rec(stripped, &[], &mut ci_path)
+                }
+                None => {
+                    self.find_case_insensitive_path_rec(&format!("{}/", path), &[], &mut ci_path)
+                }
+            };
+        }
+
+        let ci_path =
+            String::from_utf8(ci_path).expect("the corrected path is made of complete runes");
+        (ci_path, found)
     }
 
-    /// recursive case-insensitive lookup function used by n.findCaseInsensitivePath
+    /// Like `find_case_insensitive_path`, but for a path which is not known to
+    /// be valid UTF-8. Invalid paths are rejected with a `LookupError`.
+    pub fn find_case_insensitive_path_bytes(
+        &self,
+        path: &[u8],
+        fix_trailing_slash: bool,
+    ) -> Result<(String, bool), LookupError> {
+        Ok(self.find_case_insensitive_path(check_utf8(path)?, fix_trailing_slash))
+    }
+
+    /// recursive case-insensitive lookup function used by find_case_insensitive_path.
+    /// Node paths may end in the middle of a rune, its first bytes are passed
+    /// on to the child in rune_start. The case-corrected path is appended to
+    /// ci_path, which is left unchanged if nothing is found.
     fn find_case_insensitive_path_rec(
         &self,
-        mut path: &[u8],
-        mut lo_path: &[u8],
+        path: &str,
+        rune_start: &[u8],
         ci_path: &mut Vec<u8>,
-        mut rb: [u8; 4],
-        fix_trailing_slash: bool,
     ) -> bool {
-        // println!("{:?}", self.path);
-        // let n_path = str::from_utf8(&self.path).expect("ivalid utf8").to_lowercase();
-        // let lo_n_path = n_path.as_bytes();
-        let lo_n_path: Vec<u8> = self.path.iter().map(|u| u.to_ascii_lowercase()).collect();
+        let len = ci_path.len();
 
-        if lo_path.len() >= lo_n_path.len()
-            && (lo_n_path.len() == 0 || lo_path[1..lo_n_path.len()] == lo_n_path[1..])
-        {
-            // println!("self.path = {}", str::from_utf8(&self.path).unwrap());
-            ci_path.append(&mut self.path.clone());
-
-            path = &path[self.path.len()..];
-
-            if path.len() > 0 {
-                let lo_old = lo_path.clone();
-                lo_path = &lo_path[lo_n_path.len()..];
-
-                // If this node does not have a wildcard (param or catchAll) child,
-                // we can just look up the next child node and continue to walk down
-                // the tree
-                if !self.wild_child {
-                    // skip rune bytes already processed
-                    rb = shift_n_rune_bytes(rb, lo_n_path.len());
-
-                    if rb[0] != 0 {
-                        // old rune not finished
-                        for i in 0..self.indices.len() {
-                            if self.indices[i] == rb[0] {
-                                // continue with child node
-                                return self.children[i].find_case_insensitive_path_rec(
-                                    path,
-                                    lo_path,
-                                    ci_path,
-                                    rb,
-                                    fix_trailing_slash,
-                                );
-                            }
-                        }
-                    } else {
-                        // process a new rune
-                        let mut rv = 0 as char;
-
-                        // find rune start
-                        // runes are up to 4 byte long,
-                        // -4 would definitely be another rune
-                        let mut off = 0;
-                        // println!("loold {:?}", lo_old);
-                        for j in 0..min(lo_n_path.len(), 3) {
-                            let i = lo_n_path.len() - j;
-                            if rune_start(lo_old[i]) {
-                                // read rune from cached lowercase path
-                                rv = str::from_utf8(&lo_old[i..])
-                                    .unwrap()
-                                    .chars()
-                                    .next()
-                                    .unwrap();
-                                off = j;
-                                break;
-                            }
-                        }
-                        // println!("rv = {}, off = {}", rv, off);
-                        // calculate lowercase bytes of current rune
-                        rv.encode_utf8(&mut rb);
-
-                        // skipp already processed bytes
-                        rb = shift_n_rune_bytes(rb, off);
-                        // println!("rb = {:?}", rb);
-                        for i in 0..self.indices.len() {
-                            // lowercase matches
-                            if self.indices[i] == rb[0] {
-                                // must use a recursive approach since both the
-                                // uppercase byte and the lowercase byte might exist
-                                // as an index
-                                let found = self.children[i].find_case_insensitive_path_rec(
-                                    path,
-                                    lo_path,
-                                    ci_path,
-                                    rb,
-                                    fix_trailing_slash,
-                                );
-
-                                if found {
-                                    // println!("cipah = {}", str::from_utf8(&ci_path).unwrap());
-                                    return true;
-                                }
-                                if ci_path.len() > self.children[i].path.len() {
-                                    let prev_len = ci_path.len() - self.children[i].path.len();
-                                    ci_path.truncate(prev_len);
-                                }
-
-                                break;
-                            }
-                        }
+        let found = match self.n_type {
+            NodeType::Param => {
+                let end = path.find('/').unwrap_or(path.len());
 
-                        // same for uppercase rune, if it differs
-                        let up = rv.to_ascii_uppercase();
-                        if up != rv {
-                            up.encode_utf8(&mut rb);
-                            rb = shift_n_rune_bytes(rb, off);
-
-                            for i in 0..self.indices.len() {
-                                if self.indices[i] == rb[0] {
-                                    return self.children[i].find_case_insensitive_path_rec(
-                                        path,
-                                        lo_path,
-                                        ci_path,
-                                        rb,
-                                        fix_trailing_slash,
-                                    );
-                                }
-                            }
-                        }
+                if let Some(ref constraint) = self.constraint {
+                    if !constraint.is_match(&path.as_bytes()[..end]) {
+                        return false;
                     }
-
-                    // Nothing found. We can recommend to redirect to the same URL
-				    // without a trailing slash if a leaf exists for that path
-                    return fix_trailing_slash && path == [b'/'] && self.handle.is_some();
                 }
 
-                return self.children[0].find_case_insensitive_path_rec_match(
-                    path,
-                    lo_path,
-                    ci_path,
-                    rb,
-                    fix_trailing_slash,
-                );
-            } else {
-                if self.handle.is_some() {
-                    return true;
+                ci_path.extend_from_slice(&path.as_bytes()[..end]);
+
+                if end < path.len() {
+                    self.find_case_insensitive_child(&path[end..], ci_path)
+                } else {
+                    self.handle.is_some()
                 }
+            }
+            NodeType::CatchAll => {
+                ci_path.extend_from_slice(path.as_bytes());
+                self.handle.is_some()
+            }
+            _ => {
+                let n_path = [rune_start, &self.path].concat();
 
-                if fix_trailing_slash {
-                    for i in 0..self.indices.len() {
-                        if self.indices[i] == b'/' {
-                            if (self.children[i].path.len() == 1
-                                && self.children[i].handle.is_some())
-                                || (self.children[i].n_type == NodeType::CatchAll
-                                    && self.children[i].children[0].handle.is_some())
-                            {
-                                ci_path.push(b'/');
-                                return true;
-                            }
-                            return false;
-                        }
+                // only complete runes can be compared
+                let complete = match str::from_utf8(&n_path) {
+                    Ok(s) => s.len(),
+                    Err(err) => err.valid_up_to(),
+                };
+
+                let runes =
+                    str::from_utf8(&n_path[..complete]).expect("valid up to the incomplete rune");
+
+                let mut rest = path;
+                for c in runes.chars() {
+                    match rest.chars().next() {
+                        Some(pc) if eq_fold(c, pc) => rest = &rest[pc.len_utf8()..],
+                        _ => return false,
                     }
                 }
-                return false;
+
+                ci_path.extend_from_slice(&n_path[..complete]);
+
+                if complete < n_path.len() {
+                    // the rune is continued by a static child
+                    let rune_start = &n_path[complete..];
+                    self.children[..self.indices.len()].iter().any(|child| {
+                        child.find_case_insensitive_path_rec(rest, rune_start, ci_path)
+                    })
+                } else if rest.is_empty() {
+                    self.handle.is_some()
+                } else {
+                    self.find_case_insensitive_child(rest, ci_path)
+                }
             }
+        };
+
+        if !found {
+            ci_path.truncate(len);
         }
+        found
+    }
 
-        if fix_trailing_slash {
-            if path == [b'/'] {
-                return true;
-            }
-            if lo_path.len() + 1 == lo_n_path.len()
-                && lo_n_path[lo_path.len()] == b'/'
-                && lo_path[1..] == lo_n_path[1..lo_path.len()]
-                && self.handle.is_some()
-            {
-                ci_path.append(&mut self.path.clone());
+    /// case-insensitive counterpart of get_child_value
+    fn find_case_insensitive_child(&self, path: &str, ci_path: &mut Vec<u8>) -> bool {
+        // must try all static children since both the uppercase and the
+        // lowercase byte might exist as an index
+        let found = self.children[..self.indices.len()]
+            .iter()
+            .any(|child| child.find_case_insensitive_path_rec(path, &[], ci_path));
+        if found {
+            return true;
+        }
+
+        if let Some(pos) = self.param_pos() {
+            if self.children[pos].find_case_insensitive_path_rec(path, &[], ci_path) {
                 return true;
             }
         }
 
-        false
+        match self.catch_all_pos() {
+            Some(pos) if path.starts_with('/') => {
+                self.children[pos].find_case_insensitive_path_rec(path, &[], ci_path)
+            }
+            _ => false,
+        }
     }
+}
 
-    /// recursive case-insensitive lookup function used by n.findCaseInsensitivePath
-    fn find_case_insensitive_path_rec_match(
-        &self,
-        mut path: &[u8],
-        mut lo_path: &[u8],
-        ci_path: &mut Vec<u8>,
-        rb: [u8; 4],
-        fix_trailing_slash: bool,
-    ) -> bool {
-        match self.n_type {
-            NodeType::Param => {
-                let mut k = 0;
-                while k < path.len() && path[k] != b'/' {
-                    k += 1;
-                }
-                let mut path_k = path[..k].to_vec();
-                ci_path.append(&mut path_k);
-
-                if k < path.len() {
-                    if self.children.len() > 0 {
-                        lo_path = &lo_path[k..];
-                        path = &path[k..];
-
-                        return self.children[0].find_case_insensitive_path_rec(
-                            path,
-                            lo_path,
-                            ci_path,
-                            rb,
-                            fix_trailing_slash,
-                        );
-                    }
+/// Iterator over the routes of a tree, see `Node::routes`.
+pub struct Routes<'a, T> {
+    // nodes left to visit with the path leading to them
+    stack: Vec<(&'a Node<T>, Vec<u8>)>,
+}
 
-                    if fix_trailing_slash && path.len() == k + 1 {
-                        return true;
-                    }
-                    return false;
-                }
+impl<'a, T> Iterator for Routes<'a, T> {
+    type Item = (String, &'a T);
 
-                if self.handle.is_some() {
-                    return true;
-                } else if fix_trailing_slash && self.children.len() == 1 {
-                    if self.children[0].path == [b'/'] && self.children[0].handle.is_some() {
-                        ci_path.push(b'/');
-                        return true;
-                    }
-                }
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, mut path)) = self.stack.pop() {
+            path.extend_from_slice(&node.path);
 
-                return false;
+            // push in reverse so that children are visited in order
+            for child in node.children.iter().rev() {
+                self.stack.push((child, path.clone()));
             }
-            NodeType::CatchAll => {
-                ci_path.append(&mut path.to_vec());
-                return true;
+
+            if let Some(ref handle) = node.handle {
+                return Some((String::from_utf8_lossy(&path).into_owned(), handle));
             }
-            _ => panic!("invalid node type"),
         }
+
+        None
     }
 }
 
-fn shift_n_rune_bytes(rb: [u8; 4], n: usize) -> [u8; 4] {
-    match n {
-        0 => rb,
-        1 => [rb[1], rb[2], rb[3], 0],
-        2 => [rb[2], rb[3], 0, 0],
-        3 => [rb[3], 0, 0, 0],
-        _ => [0; 4],
+/// Returns the length of the static prefix of a route, which ends in front of
+/// the first param or in front of the '/' of a catch-all.
+fn static_prefix_len(path: &[u8]) -> usize {
+    match path.iter().position(|&c| c == b':' || c == b'*') {
+        Some(i) if path[i] == b'*' => i - 1,
+        Some(i) => i,
+        None => path.len(),
     }
 }
 
-/// This function is ported from go.
-fn rune_start(b: u8) -> bool {
-    b & 0xC0 != 0x80
+/// Reports whether both chars are equal under simple case folding.
+fn eq_fold(a: char, b: char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     // use hyper::{Body, Request, Response};
-    use crate::router::Params;
+    use crate::router::{Param, Params};
     use std::panic;
     use std::sync::Mutex;
 
@@ -914,6 +1231,16 @@ mod tests {
             if ps != request.ps {
                 panic!("Params mismatch for route '{}'", request.path);
             }
+
+            // the borrowed lookup must find the same
+            let m = tree.get_value_ref(request.path);
+            assert_eq!(handler.map(|h| h()), m.handle.map(|h| h()));
+            assert_eq!(
+                ps,
+                Params::from(m.params),
+                "borrowed params for route '{}'",
+                request.path
+            );
         }
     }
 
@@ -1057,12 +1384,7 @@ mod tests {
                     "/cmd/:tool/",
                     Params(vec![Param::new("tool", "test")]),
                 ),
-                TestRequest::new(
-                    "/cmd/test",
-                    true,
-                    "",
-                    Params(vec![Param::new("tool", "test")]),
-                ),
+                TestRequest::new("/cmd/test", true, "", Params::new()),
                 TestRequest::new(
                     "/cmd/test/3",
                     false,
@@ -1088,226 +1410,772 @@ mod tests {
                     "/search/:query",
                     Params(vec![Param::new("query", "someth!ng+in+ünìcodé")]),
                 ),
+                TestRequest::new("/search/someth!ng+in+ünìcodé/", true, "", Params::new()),
+                TestRequest::new(
+                    "/user_gopher",
+                    false,
+                    "/user_:name",
+                    Params(vec![Param::new("name", "gopher")]),
+                ),
+                TestRequest::new(
+                    "/user_gopher/about",
+                    false,
+                    "/user_:name/about",
+                    Params(vec![Param::new("name", "gopher")]),
+                ),
+                TestRequest::new(
+                    "/files/js/inc/framework.js",
+                    false,
+                    "/files/:dir/*filepath",
+                    Params(vec![
+                        Param::new("dir", "js"),
+                        Param::new("filepath", "/inc/framework.js"),
+                    ]),
+                ),
+                TestRequest::new(
+                    "/info/gordon/public",
+                    false,
+                    "/info/:user/public",
+                    Params(vec![Param::new("user", "gordon")]),
+                ),
+                TestRequest::new(
+                    "/info/gordon/project/go",
+                    false,
+                    "/info/:user/project/:project",
+                    Params(vec![
+                        Param::new("user", "gordon"),
+                        Param::new("project", "go"),
+                    ]),
+                ),
+            ],
+        );
+
+        check_priorities(&mut tree);
+        check_max_params(&mut tree);
+    }
+
+    // path: &str, conflict: bool
+    type TestRoute = (&'static str, bool);
+
+    fn test_routes(routes: Vec<TestRoute>) {
+        let tree = Mutex::new(Node::new());
+        // let mut tree = Node::new();
+
+        for route in routes {
+            let recv = panic::catch_unwind(|| {
+                let mut guard = match tree.lock() {
+                    Ok(guard) => guard,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                guard.add_route(route.0, ());
+            });
+
+            if route.1 {
+                if recv.is_ok() {
+                    panic!("no panic for conflicting route '{}'", route.0);
+                }
+            } else if recv.is_err() {
+                panic!("unexpected panic for route '{}': {:?}", route.0, recv);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tree_wildcard_conflict() {
+        let routes = vec![
+            ("/cmd/:tool/:sub", false),
+            ("/cmd/vet", false),
+            ("/cmd/:tool/:subcmd", true),
+            ("/cmd/:command", true),
+            ("/src/*filepath", false),
+            ("/src/*filepathx", true),
+            ("/src/", false),
+            ("/src1/", false),
+            ("/src1/*filepath", false),
+            ("/src2*filepath", true),
+            ("/search/:query", false),
+            ("/search/invalid", false),
+            ("/search/:q", true),
+            ("/user_:name", false),
+            ("/user_x", false),
+            // ("/user_:name", false),
+            ("/user_:name", true), // Rust is different. Nil handler was not allowed. Or maybe it is a feature?
+            ("/id:id", false),
+            ("/id/:id", false),
+        ];
+        test_routes(routes);
+    }
+
+    #[test]
+    fn test_tree_child_conflict() {
+        // static children, params and catch-alls may share a position
+        let routes = vec![
+            ("/cmd/vet", false),
+            ("/cmd/:tool/:sub", false),
+            ("/src/AUTHORS", false),
+            ("/src/*filepath", false),
+            ("/user_x", false),
+            ("/user_:name", false),
+            ("/id/:id", false),
+            ("/id:id", false),
+            ("/:id", false),
+            ("/*filepath", false),
+        ];
+
+        test_routes(routes);
+    }
+
+    #[test]
+    fn test_tree_static_param_priority() {
+        let mut tree = Node::new();
+
+        let routes = vec![
+            "/users/new",
+            "/users/:id",
+            "/users/:id/edit",
+            "/users/*rest",
+            "/files/:name<[a-z]+>",
+            "/files/*filepath",
+            "/",
+            "/:page",
+            "/*filepath",
+        ];
+
+        for route in routes {
+            tree.add_route(route, fake_handler(route));
+        }
+
+        check_requests(
+            &mut tree,
+            vec![
+                TestRequest::new("/users/new", false, "/users/new", Params::new()),
+                TestRequest::new(
+                    "/users/42",
+                    false,
+                    "/users/:id",
+                    Params(vec![Param::new("id", "42")]),
+                ),
+                TestRequest::new(
+                    "/users/news",
+                    false,
+                    "/users/:id",
+                    Params(vec![Param::new("id", "news")]),
+                ),
+                // the static branch dead-ends, so the param is tried next
+                TestRequest::new(
+                    "/users/new/edit",
+                    false,
+                    "/users/:id/edit",
+                    Params(vec![Param::new("id", "new")]),
+                ),
+                // as does the param branch, so the catch-all is left
+                TestRequest::new(
+                    "/users/42/edit/x",
+                    false,
+                    "/users/*rest",
+                    Params(vec![Param::new("rest", "/42/edit/x")]),
+                ),
+                TestRequest::new(
+                    "/users/",
+                    false,
+                    "/users/*rest",
+                    Params(vec![Param::new("rest", "/")]),
+                ),
+                TestRequest::new(
+                    "/files/readme",
+                    false,
+                    "/files/:name<[a-z]+>",
+                    Params(vec![Param::new("name", "readme")]),
+                ),
+                // a failing constraint falls through to the catch-all
+                TestRequest::new(
+                    "/files/readme.md",
+                    false,
+                    "/files/*filepath",
+                    Params(vec![Param::new("filepath", "/readme.md")]),
+                ),
+                TestRequest::new("/", false, "/", Params::new()),
                 TestRequest::new(
-                    "/search/someth!ng+in+ünìcodé/",
-                    true,
-                    "",
+                    "/about",
+                    false,
+                    "/:page",
+                    Params(vec![Param::new("page", "about")]),
+                ),
+                TestRequest::new(
+                    "/users",
+                    false,
+                    "/:page",
+                    Params(vec![Param::new("page", "users")]),
+                ),
+                TestRequest::new(
+                    "/about/team",
+                    false,
+                    "/*filepath",
+                    Params(vec![Param::new("filepath", "/about/team")]),
+                ),
+            ],
+        );
+
+        check_priorities(&mut tree);
+        check_max_params(&mut tree);
+
+        for route in &["/users/:id", "/users/new", "/*filepath", "/:page"] {
+            assert!(tree.remove_route(route).is_some());
+            check_priorities(&mut tree);
+            check_max_params(&mut tree);
+        }
+
+        check_requests(
+            &mut tree,
+            vec![
+                TestRequest::new(
+                    "/users/new",
+                    false,
+                    "/users/*rest",
+                    Params(vec![Param::new("rest", "/new")]),
+                ),
+                TestRequest::new(
+                    "/users/new/edit",
+                    false,
+                    "/users/:id/edit",
+                    Params(vec![Param::new("id", "new")]),
+                ),
+                TestRequest::new(
+                    "/users/42",
+                    false,
+                    "/users/*rest",
+                    Params(vec![Param::new("rest", "/42")]),
+                ),
+                TestRequest::new("/about", true, "", Params::new()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_tree_duplicate_path() {
+        let tree = Mutex::new(Node::new());
+
+        let routes = vec![
+            "/",
+            "/doc/",
+            "/src/*filepath",
+            "/search/:query",
+            "/user_:name",
+        ];
+
+        for route in routes {
+            let mut recv = panic::catch_unwind(|| {
+                let mut guard = match tree.lock() {
+                    Ok(guard) => guard,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                guard.add_route(route, fake_handler(route));
+            });
+
+            if recv.is_err() {
+                panic!("panic inserting route '{}': {:?}", route, recv);
+            }
+
+            recv = panic::catch_unwind(|| {
+                let mut guard = match tree.lock() {
+                    Ok(guard) => guard,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                guard.add_route(route, fake_handler(route));
+            });
+
+            if recv.is_ok() {
+                panic!("no panic while inserting duplicate route '{}'", route);
+            }
+        }
+
+        check_requests(
+            &mut tree.lock().unwrap_or_else(|poisoned| poisoned.into_inner()),
+            vec![
+                TestRequest::new("/", false, "/", Params::new()),
+                TestRequest::new("/doc/", false, "/doc/", Params::new()),
+                TestRequest::new(
+                    "/src/some/file.png",
+                    false,
+                    "/src/*filepath",
+                    Params(vec![Param::new("filepath", "/some/file.png")]),
+                ),
+                TestRequest::new(
+                    "/search/someth!ng+in+ünìcodé",
+                    false,
+                    "/search/:query",
                     Params(vec![Param::new("query", "someth!ng+in+ünìcodé")]),
                 ),
                 TestRequest::new(
                     "/user_gopher",
                     false,
-                    "/user_:name",
-                    Params(vec![Param::new("name", "gopher")]),
+                    "/user_:name",
+                    Params(vec![Param::new("name", "gopher")]),
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_empty_wildcard_name() {
+        let tree = Mutex::new(Node::new());
+        let routes = vec!["/user:", "/user:/", "/cmd/:/", "/src/*"];
+
+        for route in routes {
+            let recv = panic::catch_unwind(|| {
+                let mut guard = match tree.lock() {
+                    Ok(guard) => guard,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                guard.add_route(route, fake_handler(route));
+            });
+
+            if recv.is_ok() {
+                panic!(
+                    "no panic while inserting route with empty wildcard name '{}",
+                    route
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tree_catch_all_conflict() {
+        let routes = vec![
+            ("/src/*filepath/x", true),
+            ("/src2/", false),
+            ("/src2/*filepath/x", true),
+        ];
+
+        test_routes(routes);
+    }
+
+    #[test]
+    fn test_tree_catch_all_conflict_root() {
+        let routes = vec![("/", false), ("/*filepath", false), ("/*path", true)];
+
+        test_routes(routes);
+    }
+
+    #[test]
+    fn test_tree_insert_error_unchanged() {
+        let mut tree = Node::new();
+        for route in [
+            "/",
+            "/cmd/:tool/:sub",
+            "/cmd/:tool/",
+            "/src/*filepath",
+            "/user_:name/about",
+            "/doc/go",
+        ] {
+            tree.add_route(route, route);
+        }
+
+        // a failed insertion leaves the nodes, priorities and max params as
+        // they were, even if the conflict is found deep in the tree
+        for route in [
+            "/cmd/:tool/:subcmd",
+            "/cmd/:t/x",
+            "/src/*path",
+            "/src/*filepath",
+            "/user_:id",
+            "/user_:name/about",
+            "/",
+            "/x/:id<[>",
+        ] {
+            let before = format!("{:?}", tree);
+            assert!(
+                tree.try_add_route(route, route).is_err(),
+                "no error for route '{}'",
+                route
+            );
+            assert_eq!(
+                before,
+                format!("{:?}", tree),
+                "tree changed by route '{}'",
+                route
+            );
+        }
+    }
+
+    #[test]
+    fn test_tree_insert_error() {
+        let mut tree = Node::new();
+
+        let routes = vec![
+            "/",
+            "/cmd/:tool/:sub",
+            "/src/*filepath",
+            "/search/:query",
+            "/doc/",
+            "/doc/go",
+            "/api/",
+        ];
+
+        for route in routes {
+            tree.add_route(route, fake_handler(route));
+        }
+
+        let errors = vec![
+            (
+                "/",
+                InsertError::Duplicate {
+                    path: "/".to_string(),
+                },
+            ),
+            (
+                "/cmd/:tool/:subcmd",
+                InsertError::WildcardConflict {
+                    path: "/cmd/:tool/:subcmd".to_string(),
+                    new: ":subcmd".to_string(),
+                    existing: ":sub".to_string(),
+                    prefix: "/cmd/:tool/:sub".to_string(),
+                },
+            ),
+            (
+                "/src/*path",
+                InsertError::WildcardConflict {
+                    path: "/src/*path".to_string(),
+                    new: "/*path".to_string(),
+                    existing: "/*filepath".to_string(),
+                    prefix: "/src/*filepath".to_string(),
+                },
+            ),
+            (
+                "/src/*filepath",
+                InsertError::Duplicate {
+                    path: "/src/*filepath".to_string(),
+                },
+            ),
+            (
+                "/user:",
+                InsertError::EmptyWildcardName {
+                    path: "/user:".to_string(),
+                },
+            ),
+            (
+                "/:foo:bar",
+                InsertError::TooManyWildcards {
+                    path: "/:foo:bar".to_string(),
+                    segment: ":foo:bar".to_string(),
+                },
+            ),
+            (
+                "/files/*filepath/x",
+                InsertError::CatchAllNotAtEnd {
+                    path: "/files/*filepath/x".to_string(),
+                },
+            ),
+            (
+                "/files*filepath",
+                InsertError::MissingSlashBeforeCatchAll {
+                    path: "/files*filepath".to_string(),
+                },
+            ),
+        ];
+
+        for (route, expected) in errors {
+            match tree.try_add_route(route, fake_handler(route)) {
+                Ok(()) => panic!("no error for route '{}'", route),
+                Err(err) => assert_eq!(expected, err, "wrong error for route '{}'", route),
+            }
+        }
+
+        // failed insertions must leave the tree intact
+        check_priorities(&mut tree);
+        check_max_params(&mut tree);
+        check_requests(
+            &mut tree,
+            vec![
+                TestRequest::new("/", false, "/", Params::new()),
+                TestRequest::new(
+                    "/cmd/test/3",
+                    false,
+                    "/cmd/:tool/:sub",
+                    Params(vec![Param::new("tool", "test"), Param::new("sub", "3")]),
+                ),
+                TestRequest::new(
+                    "/src/some/file.png",
+                    false,
+                    "/src/*filepath",
+                    Params(vec![Param::new("filepath", "/some/file.png")]),
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_tree_remove() {
+        let mut tree = Node::new();
+
+        let routes = vec![
+            "/",
+            "/cmd/:tool/:sub",
+            "/cmd/:tool/",
+            "/src/*filepath",
+            "/search/",
+            "/search/:query",
+            "/user_:name",
+            "/user_:name/about",
+            "/files/:dir/*filepath",
+            "/doc/",
+            "/doc/go_faq.html",
+            "/doc/go1.html",
+            "/info/:user/public",
+            "/info/:user/project/:project",
+        ];
+
+        for route in &routes {
+            tree.add_route(route, fake_handler(route));
+        }
+
+        let removed = vec![
+            "/cmd/:tool/:sub",
+            "/src/*filepath",
+            "/search/",
+            "/user_:name",
+            "/doc/go1.html",
+            "/info/:user/project/:project",
+        ];
+
+        for route in &removed {
+            match tree.remove_route(route) {
+                Some(h) => assert_eq!(*route, h()),
+                None => panic!("route '{}' not removed", route),
+            }
+            check_priorities(&mut tree);
+            check_max_params(&mut tree);
+        }
+
+        // already removed, unknown or not a registered pattern
+        for route in &[
+            "/src/*filepath",
+            "/cmd/:tool",
+            "/cmd/:tool/:subcmd",
+            "/doc/go",
+            "/nope",
+        ] {
+            assert!(tree.remove_route(route).is_none(), "removed '{}'", route);
+        }
+
+        check_requests(
+            &mut tree,
+            vec![
+                TestRequest::new("/", false, "/", Params::new()),
+                TestRequest::new(
+                    "/cmd/test/",
+                    false,
+                    "/cmd/:tool/",
+                    Params(vec![Param::new("tool", "test")]),
+                ),
+                TestRequest::new("/cmd/test/3", true, "", Params::new()),
+                TestRequest::new("/src/some/file.png", true, "", Params::new()),
+                TestRequest::new("/search/", true, "", Params::new()),
+                TestRequest::new(
+                    "/search/gopher",
+                    false,
+                    "/search/:query",
+                    Params(vec![Param::new("query", "gopher")]),
                 ),
+                TestRequest::new("/user_gopher", true, "", Params::new()),
                 TestRequest::new(
                     "/user_gopher/about",
                     false,
                     "/user_:name/about",
                     Params(vec![Param::new("name", "gopher")]),
                 ),
-                TestRequest::new(
-                    "/files/js/inc/framework.js",
-                    false,
-                    "/files/:dir/*filepath",
-                    Params(vec![
-                        Param::new("dir", "js"),
-                        Param::new("filepath", "/inc/framework.js"),
-                    ]),
-                ),
+                TestRequest::new("/doc/go_faq.html", false, "/doc/go_faq.html", Params::new()),
+                TestRequest::new("/doc/go1.html", true, "", Params::new()),
                 TestRequest::new(
                     "/info/gordon/public",
                     false,
                     "/info/:user/public",
                     Params(vec![Param::new("user", "gordon")]),
                 ),
-                TestRequest::new(
-                    "/info/gordon/project/go",
-                    false,
-                    "/info/:user/project/:project",
-                    Params(vec![
-                        Param::new("user", "gordon"),
-                        Param::new("project", "go"),
-                    ]),
-                ),
             ],
         );
 
-        check_priorities(&mut tree);
-        check_max_params(&mut tree);
-    }
-
-    // path: &str, conflict: bool
-    type TestRoute = (&'static str, bool);
-
-    fn test_routes(routes: Vec<TestRoute>) {
-        let tree = Mutex::new(Node::new());
-        // let mut tree = Node::new();
-
-        for route in routes {
-            let recv = panic::catch_unwind(|| {
-                let mut guard = match tree.lock() {
-                    Ok(guard) => guard,
-                    Err(poisoned) => poisoned.into_inner(),
-                };
-                guard.add_route(route.0, ());
-            });
+        // a route conflicting with a removed one can be added now
+        tree.add_route("/cmd/:tool/:subcmd", fake_handler("/cmd/:tool/:subcmd"));
 
-            if route.1 {
-                if recv.is_ok() {
-                    panic!("no panic for conflicting route '{}'", route.0);
-                }
-            } else if recv.is_err() {
-                panic!("unexpected panic for route '{}': {:?}", route.0, recv);
+        for route in &routes {
+            if !removed.contains(route) {
+                assert!(
+                    tree.remove_route(route).is_some(),
+                    "route '{}' not removed",
+                    route
+                );
             }
         }
-    }
+        tree.remove_route("/cmd/:tool/:subcmd");
 
-    #[test]
-    fn test_tree_wildcard_conflict() {
-        let routes = vec![
-            ("/cmd/:tool/:sub", false),
-            ("/cmd/vet", true),
-            ("/src/*filepath", false),
-            ("/src/*filepathx", true),
-            ("/src/", true),
-            ("/src1/", false),
-            ("/src1/*filepath", true),
-            ("/src2*filepath", true),
-            ("/search/:query", false),
-            ("/search/invalid", true),
-            ("/user_:name", false),
-            ("/user_x", true),
-            // ("/user_:name", false),
-            ("/user_:name", true), // Rust is different. Nil handler was not allowed. Or maybe it is a feature?
-            ("/id:id", false),
-            ("/id/:id", true),
-        ];
-        test_routes(routes);
+        assert!(tree.is_empty());
+        check_priorities(&mut tree);
+        check_max_params(&mut tree);
+
+        tree.add_route("/:all", fake_handler("/:all"));
+        check_requests(
+            &mut tree,
+            vec![TestRequest::new(
+                "/x",
+                false,
+                "/:all",
+                Params(vec![Param::new("all", "x")]),
+            )],
+        );
     }
 
     #[test]
-    fn test_tree_child_conflict() {
-        let routes = vec![
-            ("/cmd/vet", false),
-            ("/cmd/:tool/:sub", true),
-            ("/src/AUTHORS", false),
-            ("/src/*filepath", true),
-            ("/user_x", false),
-            ("/user_:name", true),
-            ("/id/:id", false),
-            ("/id:id", true),
-            ("/:id", true),
-            ("/*filepath", true),
-        ];
+    fn test_tree_replace() {
+        let mut tree = Node::new();
 
-        test_routes(routes);
+        for route in &["/hi", "/user/:name", "/src/*filepath"] {
+            tree.add_route(route, fake_handler(route));
+        }
+
+        assert!(tree
+            .replace_route("/user/:name", fake_handler("new user"))
+            .is_some());
+        assert!(tree
+            .replace_route("/src/*filepath", fake_handler("new src"))
+            .is_some());
+        assert!(tree
+            .replace_route("/hello", fake_handler("/hello"))
+            .is_none());
+
+        check_priorities(&mut tree);
+        check_max_params(&mut tree);
+        check_requests(
+            &mut tree,
+            vec![
+                TestRequest::new("/hi", false, "/hi", Params::new()),
+                TestRequest::new("/hello", false, "/hello", Params::new()),
+                TestRequest::new(
+                    "/user/gopher",
+                    false,
+                    "new user",
+                    Params(vec![Param::new("name", "gopher")]),
+                ),
+                TestRequest::new(
+                    "/src/x",
+                    false,
+                    "new src",
+                    Params(vec![Param::new("filepath", "/x")]),
+                ),
+            ],
+        );
     }
 
     #[test]
-    fn test_tree_duplicate_path() {
-        let tree = Mutex::new(Node::new());
+    fn test_tree_routes() {
+        let mut tree = Node::new();
+        assert_eq!(0, tree.routes().count());
 
-        let routes = vec![
+        let mut routes = vec![
             "/",
-            "/doc/",
+            "/cmd/:tool/:sub",
+            "/cmd/:tool/",
             "/src/*filepath",
+            "/search/",
             "/search/:query",
             "/user_:name",
+            "/user_:name/about",
+            "/files/:dir/*filepath",
+            "/doc/",
+            "/doc/go_faq.html",
+            "/doc/go1.html",
+            "/α",
+            "/β",
         ];
 
-        for route in routes {
-            let mut recv = panic::catch_unwind(|| {
-                let mut guard = match tree.lock() {
-                    Ok(guard) => guard,
-                    Err(poisoned) => poisoned.into_inner(),
-                };
-                guard.add_route(route, fake_handler(route));
-            });
+        for route in &routes {
+            tree.add_route(route, fake_handler(route));
+        }
 
-            if recv.is_err() {
-                panic!("panic inserting route '{}': {:?}", route, recv);
-            }
+        let mut found: Vec<String> = Vec::new();
+        for (path, handle) in tree.routes() {
+            assert_eq!(path, handle());
+            found.push(path);
+        }
 
-            recv = panic::catch_unwind(|| {
-                let mut guard = match tree.lock() {
-                    Ok(guard) => guard,
-                    Err(poisoned) => poisoned.into_inner(),
-                };
-                guard.add_route(route, fake_handler(route));
-            });
+        routes.sort();
+        found.sort();
+        assert_eq!(routes, found);
+    }
 
-            if recv.is_ok() {
-                panic!("no panic while inserting duplicate route '{}'", route);
-            }
+    #[test]
+    fn test_tree_constraint() {
+        let mut tree = Node::new();
+
+        let routes = vec![
+            "/users/:id<\\d+>",
+            "/users/:id<\\d+>/posts/:slug<[a-z0-9_-]+>",
+            "/files/:name<[^/]*\\.json>/raw",
+            "/src/*filepath",
+        ];
+
+        for route in &routes {
+            tree.add_route(route, fake_handler(route));
         }
 
         check_requests(
-            &mut tree.lock().unwrap_or_else(|poisoned| poisoned.into_inner()),
+            &mut tree,
             vec![
-                TestRequest::new("/", false, "/", Params::new()),
-                TestRequest::new("/doc/", false, "/doc/", Params::new()),
                 TestRequest::new(
-                    "/src/some/file.png",
+                    "/users/42",
                     false,
-                    "/src/*filepath",
-                    Params(vec![Param::new("filepath", "/some/file.png")]),
+                    "/users/:id<\\d+>",
+                    Params(vec![Param::new("id", "42")]),
                 ),
+                TestRequest::new("/users/gordon", true, "", Params::new()),
+                TestRequest::new("/users/42x", true, "", Params::new()),
                 TestRequest::new(
-                    "/search/someth!ng+in+ünìcodé",
+                    "/users/42/posts/hello_world-1",
                     false,
-                    "/search/:query",
-                    Params(vec![Param::new("query", "someth!ng+in+ünìcodé")]),
+                    "/users/:id<\\d+>/posts/:slug<[a-z0-9_-]+>",
+                    Params(vec![
+                        Param::new("id", "42"),
+                        Param::new("slug", "hello_world-1"),
+                    ]),
                 ),
+                TestRequest::new("/users/42/posts/Hello", true, "", Params::new()),
                 TestRequest::new(
-                    "/user_gopher",
+                    "/files/a.json/raw",
                     false,
-                    "/user_:name",
-                    Params(vec![Param::new("name", "gopher")]),
+                    "/files/:name<[^/]*\\.json>/raw",
+                    Params(vec![Param::new("name", "a.json")]),
                 ),
+                TestRequest::new("/files/a.xml/raw", true, "", Params::new()),
             ],
         );
-    }
-
-    #[test]
-    fn test_empty_wildcard_name() {
-        let tree = Mutex::new(Node::new());
-        let routes = vec!["/user:", "/user:/", "/cmd/:/", "/src/*"];
 
-        for route in routes {
-            let recv = panic::catch_unwind(|| {
-                let mut guard = match tree.lock() {
-                    Ok(guard) => guard,
-                    Err(poisoned) => poisoned.into_inner(),
-                };
-                guard.add_route(route, fake_handler(route));
-            });
+        check_priorities(&mut tree);
+        check_max_params(&mut tree);
 
-            if recv.is_ok() {
-                panic!(
-                    "no panic while inserting route with empty wildcard name '{}",
-                    route
-                );
+        let (out, found) = tree.find_case_insensitive_path("/USERS/42/Posts/x", false);
+        assert!(found);
+        assert_eq!("/users/42/posts/x", out);
+        assert!(!tree.find_case_insensitive_path("/USERS/x", false).1);
+
+        let errors = vec![
+            "/a/:id<>",
+            "/b/:id<\\d+",
+            "/c/:id<(>",
+            "/d/*path<.*>",
+            "/e/:id<\\d+>x",
+        ];
+        for route in errors {
+            match tree.try_add_route(route, fake_handler(route)) {
+                Err(InsertError::InvalidConstraint { .. }) => {}
+                res => panic!("no constraint error for route '{}': {:?}", route, res),
             }
         }
-    }
-
-    #[test]
-    fn test_tree_catch_all_conflict() {
-        let routes = vec![
-            ("/src/*filepath/x", true),
-            ("/src2/", false),
-            ("/src2/*filepath/x", true),
-        ];
-
-        test_routes(routes);
-    }
-
-    #[test]
-    fn test_tree_catch_all_conflict_root() {
-        let routes = vec![("/", false), ("/*filepath", true)];
 
-        test_routes(routes);
+        // a different constraint is a different wildcard
+        match tree.try_add_route("/users/:id<[a-z]+>", fake_handler("")) {
+            Err(InsertError::WildcardConflict { .. }) => {}
+            res => panic!("no wildcard conflict: {:?}", res),
+        }
     }
 
     #[test]
@@ -1327,7 +2195,7 @@ mod tests {
 
             // [TODO] Not strict enough
             if recv.is_ok() {
-                panic!(panic_msg);
+                panic!("{}", panic_msg);
             }
         }
     }
@@ -1523,5 +2391,159 @@ mod tests {
                 panic!("Wrong result for route '{}': {}", route, out);
             }
         }
+
+        // path, expected out, found, fix trailing slash
+        let tests = vec![
+            ("/HI", "/hi", true, false),
+            ("/HI/", "/hi", true, true),
+            ("/B/", "/b/", true, false),
+            ("/B", "/b/", true, true),
+            ("/abc/", "/ABC/", true, false),
+            ("/aBc", "/ABC/", true, true),
+            ("/SEARCH/QUERY", "/search/QUERY", true, false),
+            ("/CMD/TOOL/", "/cmd/TOOL/", true, false),
+            ("/CMD/TOOL", "/cmd/TOOL/", true, true),
+            ("/SRC/FILE/PATH", "/src/FILE/PATH", true, false),
+            ("/x/Y", "/x/y", true, false),
+            ("/0/ID/1", "/0/ID/1", true, false),
+            ("/π", "/Π", true, false),
+            ("/U/ÄPFÊL/", "/u/äpfêl/", true, false),
+            ("/u/ÖPFÊL/", "/u/öpfêl", true, true),
+            ("/V/äPFÊL", "/v/Äpfêl/", true, true),
+            ("/W/♭", "/w/♭/", true, true),
+            ("/HI/", "", false, false),
+            ("/NO", "", false, true),
+            ("/W/♮", "", false, true),
+        ];
+
+        for (path, expected, expected_found, fix_trailing_slash) in tests {
+            let (out, found) = tree.find_case_insensitive_path(path, fix_trailing_slash);
+            assert_eq!(expected_found, found, "wrong found for '{}'", path);
+            if found {
+                assert_eq!(expected, out, "wrong result for '{}'", path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tree_get_value_ref() {
+        let mut tree = Node::new();
+
+        for route in &[
+            "/hi",
+            "/users/:id/posts/:slug",
+            "/src/*filepath",
+            "/a/:b/:c/:d/:e/:f",
+        ] {
+            tree.add_route(route, fake_handler(route));
+        }
+
+        let path = String::from("/users/42/posts/hello");
+        let m = tree.get_value_ref(&path);
+        assert_eq!(
+            Some("/users/:id/posts/:slug".to_string()),
+            m.handle.map(|h| h())
+        );
+        assert_eq!(2, m.params.len());
+        assert_eq!(Some("42"), m.params.by_name("id"));
+        assert_eq!("hello", &m.params[1]);
+        assert_eq!(
+            vec![("id", "42"), ("slug", "hello")],
+            m.params.iter().collect::<Vec<_>>()
+        );
+        assert!(!m.tsr);
+
+        // the values point into the path
+        let value = m.params.by_name("slug").unwrap();
+        assert_eq!(path[16..].as_ptr(), value.as_ptr());
+
+        let m = tree.get_value_ref("/src/some/file.png");
+        assert_eq!(Some("/some/file.png"), m.params.by_name("filepath"));
+
+        // more params than fit inline
+        let m = tree.get_value_ref("/a/1/2/3/4/5");
+        assert_eq!(
+            vec!["1", "2", "3", "4", "5"],
+            m.params.iter().map(|(_, v)| v).collect::<Vec<_>>()
+        );
+
+        let m = tree.get_value_ref("/hi/");
+        assert!(m.handle.is_none());
+        assert!(m.params.is_empty());
+        assert!(m.tsr);
+    }
+
+    #[test]
+    fn test_tree_lookup_arbitrary_bytes() {
+        let mut tree = Node::new();
+
+        let routes = vec![
+            "/",
+            "/hi",
+            "/users/new",
+            "/users/:id<\\d+>",
+            "/users/:id<\\d+>/posts/:slug",
+            "/users/*rest",
+            "/src/*filepath",
+            "/u/äpfêl/",
+            "/w/♬",
+            "/w/♭/",
+            "/w/𠜎",
+            "/Π",
+            "/:page",
+        ];
+
+        for route in &routes {
+            tree.add_route(route, fake_handler(route));
+        }
+
+        // bytes likely to hit edge cases: separators, wildcards, escapes and
+        // the single bytes of multi-byte chars
+        let alphabet: Vec<u8> = [
+            "/:*<>%.aAhHiInNsSuUwW09".as_bytes(),
+            "äÄêπΠ♬♭𠜎".as_bytes(),
+            &[0x00, 0x80, 0xBF, 0xC0, 0xF5, 0xFF],
+        ]
+        .concat();
+
+        // xorshift, so the test is deterministic
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..20_000 {
+            let len = (next() % 24) as usize;
+            let mut path: Vec<u8> = (0..len)
+                .map(|_| alphabet[(next() % alphabet.len() as u64) as usize])
+                .collect();
+            if next() % 2 == 0 {
+                path.insert(0, b'/');
+            }
+
+            let valid = str::from_utf8(&path).is_ok();
+            let value = tree.get_value_bytes(&path);
+            let ci = tree.find_case_insensitive_path_bytes(&path, true);
+            assert_eq!(valid, value.is_ok(), "{:?}", path);
+            assert_eq!(valid, ci.is_ok(), "{:?}", path);
+
+            let lossy = String::from_utf8_lossy(&path);
+            tree.get_value(&lossy);
+            tree.find_case_insensitive_path(&lossy, false);
+        }
+
+        assert_eq!(
+            Err(LookupError::InvalidUtf8 { valid_up_to: 3 }),
+            tree.get_value_bytes(b"/hi\xFF")
+                .map(|(h, _, _)| h.map(|h| h()))
+        );
+        assert_eq!(
+            Ok((Some("/hi".to_string()), false)),
+            tree.get_value_bytes(b"/hi")
+                .map(|(h, _, tsr)| (h.map(|h| h()), tsr))
+        );
     }
 }